@@ -0,0 +1,138 @@
+#!/usr/bin/env perl
+# Regenerates the Unicode tables in src/unicode from Perl's Unicode::UCD.
+#
+# Usage: perl scripts/generate-unicode-tables.pl
+
+use strict;
+use warnings;
+
+use File::Basename qw(dirname);
+use File::Spec::Functions qw(catfile);
+use Unicode::UCD qw(all_casefolds prop_invlist prop_value_aliases prop_values);
+
+my $UNICODE_VERSION = '14.0.0';
+die "expected Unicode $UNICODE_VERSION, Unicode::UCD has ", Unicode::UCD::UnicodeVersion(), "\n"
+    unless Unicode::UCD::UnicodeVersion() eq $UNICODE_VERSION;
+
+my $output_dir = catfile(dirname(__FILE__), '..', 'src', 'unicode');
+
+sub char_literal {
+    my ($code_point) = @_;
+    return sprintf "'\\u{%X}'", $code_point;
+}
+
+sub header {
+    my ($property) = @_;
+    return "// Generated by scripts/generate-unicode-tables.pl from the Unicode $UNICODE_VERSION"
+        . " $property property.\n\n";
+}
+
+sub write_file {
+    my ($name, $contents) = @_;
+    my $path = catfile($output_dir, $name);
+    open my $file, '>', $path or die "cannot write $path: $!\n";
+    print $file $contents;
+    close $file;
+    system('rustfmt', '--edition', '2018', $path) == 0 or die "rustfmt failed on $path\n";
+}
+
+sub case_folding {
+    my $casefolds = all_casefolds();
+    my %orbits;
+    for my $code_point (keys %$casefolds) {
+        my $casefold = $casefolds->{$code_point};
+        next unless $casefold->{status} eq 'C' || $casefold->{status} eq 'S';
+        my $folded = hex $casefold->{mapping};
+        $orbits{$folded}{$folded} = 1;
+        $orbits{$folded}{$code_point} = 1;
+    }
+    # ASCII letters only fold to each other, so an orbit such as {K, k, KELVIN SIGN} is split
+    # into {K, k} and {KELVIN SIGN}. Each part is closed, so folding is the same from every member.
+    my %equivalents;
+    for my $orbit (values %orbits) {
+        my @members = sort { $a <=> $b } keys %$orbit;
+        for my $member (@members) {
+            my $is_ascii = $member < 0x80;
+            my @equivalents = grep { $_ != $member && ($_ < 0x80) == $is_ascii } @members;
+            $equivalents{$member} = \@equivalents if @equivalents;
+        }
+    }
+    my $contents = header('Simple_Case_Folding');
+    $contents .= "pub const SIMPLE_CASE_FOLDING: &[(char, &[char])] = &[\n";
+    for my $code_point (sort { $a <=> $b } keys %equivalents) {
+        $contents .= sprintf "(%s, &[%s]),\n", char_literal($code_point),
+            join(', ', map { char_literal($_) } @{$equivalents{$code_point}});
+    }
+    $contents .= "];\n";
+    write_file('case_folding.rs', $contents);
+}
+
+sub normalize {
+    my ($name) = @_;
+    $name =~ s/[ _-]//g;
+    return lc $name;
+}
+
+sub ranges {
+    my @invlist = @_;
+    push @invlist, 0x110000 if @invlist % 2;
+    my @ranges;
+    while (my ($start, $end) = splice @invlist, 0, 2) {
+        $end -= 1;
+        if ($start < 0xD800 && $end > 0xDFFF) {
+            push @ranges, [$start, 0xD7FF], [0xE000, $end];
+        } elsif ($start >= 0xD800 && $start <= 0xDFFF) {
+            push @ranges, [0xE000, $end] if $end > 0xDFFF;
+        } elsif ($end >= 0xD800 && $end <= 0xDFFF) {
+            push @ranges, [$start, 0xD7FF] if $start < 0xD800;
+        } else {
+            push @ranges, [$start, $end];
+        }
+    }
+    return @ranges;
+}
+
+sub property_table {
+    my ($file_name, $property, @values) = @_;
+    my %ranges_by_long_name;
+    my %const_by_name;
+    for my $value (@values) {
+        my ($invlist, @aliases) = @$value;
+        my @ranges = ranges(@$invlist);
+        next unless @ranges;
+        $ranges_by_long_name{$aliases[1]} = \@ranges;
+        $const_by_name{normalize($_)} = uc $aliases[1] for @aliases;
+    }
+    my $contents = header($property);
+    for my $long_name (sort keys %ranges_by_long_name) {
+        my $const = uc $long_name;
+        $contents .= "pub const $const: &[(char, char)] = &[\n";
+        for my $range (@{$ranges_by_long_name{$long_name}}) {
+            $contents .= sprintf "(%s, %s),\n", char_literal($range->[0]), char_literal($range->[1]);
+        }
+        $contents .= "];\n\n";
+    }
+    $contents .= "pub const BY_NAME: &[(&str, &[(char, char)])] = &[\n";
+    for my $name (sort keys %const_by_name) {
+        $contents .= "(\"$name\", $const_by_name{$name}),\n";
+    }
+    $contents .= "];\n";
+    write_file($file_name, $contents);
+}
+
+sub property_values {
+    my ($property) = @_;
+    return map {
+        [[prop_invlist("$property=$_")], prop_value_aliases($property, $_)]
+    } grep { prop_value_aliases($property, $_) } prop_values($property);
+}
+
+case_folding();
+property_table('general_category.rs', 'General_Category', property_values('gc'));
+property_table('script.rs', 'Script', property_values('sc'));
+property_table(
+    'property.rs',
+    'XID_Start and XID_Continue',
+    [[prop_invlist('XID_Start')], 'XIDS', 'XID_Start'],
+    [[prop_invlist('XID_Continue')], 'XIDC', 'XID_Continue'],
+);
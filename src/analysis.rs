@@ -0,0 +1,229 @@
+use {
+    crate::dfa::{self, Dfa, StateId, StateIdRepr},
+    std::collections::VecDeque,
+};
+
+/// Strings are matched as whole inputs, so assertions see the start of input before them and the
+/// end of input after them. The same holds for `strings` and `sampler`.
+pub fn is_language_empty<S: StateIdRepr>(dfa: &Dfa<S>, token: Option<usize>) -> bool {
+    !co_reachable_states(dfa, token)[dfa.start_state_id_after(None)]
+}
+
+pub fn is_language_finite<S: StateIdRepr>(dfa: &Dfa<S>, token: Option<usize>) -> bool {
+    let reachable_states = reachable_states(dfa);
+    let co_reachable_states = co_reachable_states(dfa, token);
+    let is_useful = |state_id: StateId| reachable_states[state_id] && co_reachable_states[state_id];
+    let mut colors = vec![Color::White; dfa.state_count()];
+    let mut state_stack = Vec::new();
+    for state_id in (0..dfa.state_count()).filter(|&state_id| is_useful(state_id)) {
+        if colors[state_id] != Color::White {
+            continue;
+        }
+        colors[state_id] = Color::Gray;
+        state_stack.push((state_id, 0));
+        while let Some((state_id, byte)) = state_stack.pop() {
+            if byte == dfa::ALPHABET_LEN {
+                colors[state_id] = Color::Black;
+                continue;
+            }
+            state_stack.push((state_id, byte + 1));
+            let next_state_id = dfa.next_state_id(state_id, byte as u8);
+            if !is_useful(next_state_id) {
+                continue;
+            }
+            match colors[next_state_id] {
+                Color::White => {
+                    colors[next_state_id] = Color::Gray;
+                    state_stack.push((next_state_id, 0));
+                }
+                Color::Gray => return false,
+                Color::Black => {}
+            }
+        }
+    }
+    true
+}
+
+pub fn count_strings<S: StateIdRepr>(
+    dfa: &Dfa<S>,
+    token: Option<usize>,
+    length: usize,
+) -> Option<u128> {
+    Some(string_counts(dfa, token, length)?[length][dfa.start_state_id_after(None)])
+}
+
+pub fn string_counts<S: StateIdRepr>(
+    dfa: &Dfa<S>,
+    token: Option<usize>,
+    max_length: usize,
+) -> Option<Vec<Vec<u128>>> {
+    let mut string_counts = Vec::with_capacity(max_length + 1);
+    string_counts.push(
+        dfa.states()
+            .map(|(_, state)| accepts(state.matched_token_before(None), token) as u128)
+            .collect::<Vec<_>>(),
+    );
+    for length in 1..=max_length {
+        let previous_counts = &string_counts[length - 1];
+        let mut counts = vec![0; dfa.state_count()];
+        for (state_id, state) in dfa.states().skip(1) {
+            for transition in state.transitions() {
+                counts[state_id] =
+                    u128::checked_add(counts[state_id], previous_counts[transition.next_state_id])?;
+            }
+        }
+        string_counts.push(counts);
+    }
+    Some(string_counts)
+}
+
+pub fn shortest_string<S: StateIdRepr>(dfa: &Dfa<S>, token: Option<usize>) -> Option<Vec<u8>> {
+    let mut previous_states = vec![None; dfa.state_count()];
+    let mut is_visited = vec![false; dfa.state_count()];
+    let start_state_id = dfa.start_state_id_after(None);
+    is_visited[start_state_id] = true;
+    let mut state_queue = VecDeque::new();
+    state_queue.push_back(start_state_id);
+    while let Some(state_id) = state_queue.pop_front() {
+        if accepts(dfa.state(state_id).matched_token_before(None), token) {
+            let mut bytes = Vec::new();
+            let mut state_id = state_id;
+            while let Some((previous_state_id, byte)) = previous_states[state_id] {
+                bytes.push(byte);
+                state_id = previous_state_id;
+            }
+            bytes.reverse();
+            return Some(bytes);
+        }
+        for transition in dfa.state(state_id).transitions() {
+            if is_visited[transition.next_state_id] {
+                continue;
+            }
+            is_visited[transition.next_state_id] = true;
+            previous_states[transition.next_state_id] = Some((state_id, transition.byte));
+            state_queue.push_back(transition.next_state_id);
+        }
+    }
+    None
+}
+
+pub fn reachable_states<S: StateIdRepr>(dfa: &Dfa<S>) -> Vec<bool> {
+    let mut reachable_states = vec![false; dfa.state_count()];
+    let start_state_id = dfa.start_state_id_after(None);
+    reachable_states[start_state_id] = true;
+    let mut state_stack = vec![start_state_id];
+    while let Some(state_id) = state_stack.pop() {
+        for transition in dfa.state(state_id).transitions() {
+            if !reachable_states[transition.next_state_id] {
+                reachable_states[transition.next_state_id] = true;
+                state_stack.push(transition.next_state_id);
+            }
+        }
+    }
+    reachable_states
+}
+
+pub fn co_reachable_states<S: StateIdRepr>(dfa: &Dfa<S>, token: Option<usize>) -> Vec<bool> {
+    let mut previous_state_ids = vec![Vec::new(); dfa.state_count()];
+    for (state_id, state) in dfa.states() {
+        for transition in state.transitions() {
+            previous_state_ids[transition.next_state_id].push(state_id);
+        }
+    }
+    let mut co_reachable_states = dfa
+        .states()
+        .map(|(_, state)| accepts(state.matched_token_before(None), token))
+        .collect::<Vec<_>>();
+    let mut state_stack = (0..dfa.state_count())
+        .filter(|&state_id| co_reachable_states[state_id])
+        .collect::<Vec<_>>();
+    while let Some(state_id) = state_stack.pop() {
+        for &previous_state_id in &previous_state_ids[state_id] {
+            if !co_reachable_states[previous_state_id] {
+                co_reachable_states[previous_state_id] = true;
+                state_stack.push(previous_state_id);
+            }
+        }
+    }
+    co_reachable_states
+}
+
+pub fn accepts(matched_token: Option<usize>, token: Option<usize>) -> bool {
+    match (matched_token, token) {
+        (Some(matched_token), Some(token)) => matched_token == token,
+        (Some(_), None) => true,
+        (None, _) => false,
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Color {
+    White,
+    Gray,
+    Black,
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{determinizer::Determinizer, parser::Parser},
+    };
+
+    fn dfa(patterns: &[&str]) -> Dfa {
+        let mut parser = Parser::new();
+        for (token, pattern) in patterns.iter().enumerate() {
+            parser.parse(pattern, token).unwrap();
+        }
+        Determinizer::new(&parser.build()).determinize()
+    }
+
+    #[test]
+    fn emptiness() {
+        let dfa = dfa(&["(a|b)+", "c"]);
+        assert!(!dfa.is_language_empty(None));
+        assert!(!dfa.is_language_empty(Some(0)));
+        assert!(!dfa.is_language_empty(Some(1)));
+        assert!(dfa.is_language_empty(Some(2)));
+    }
+
+    #[test]
+    fn finiteness() {
+        let dfa = dfa(&["a+", "b|bc"]);
+        assert!(!dfa.is_language_finite(None));
+        assert!(!dfa.is_language_finite(Some(0)));
+        assert!(dfa.is_language_finite(Some(1)));
+    }
+
+    #[test]
+    fn counts_strings_per_length() {
+        let dfa = dfa(&["(a|b)*c", "d"]);
+        assert_eq!(dfa.count_strings(Some(0), 0), Some(0));
+        assert_eq!(dfa.count_strings(Some(0), 1), Some(1));
+        assert_eq!(dfa.count_strings(Some(0), 3), Some(4));
+        assert_eq!(dfa.count_strings(Some(1), 1), Some(1));
+        assert_eq!(dfa.count_strings(Some(1), 2), Some(0));
+        assert_eq!(dfa.count_strings(None, 4), Some(8));
+    }
+
+    #[test]
+    fn assertions_see_the_ends_of_the_input() {
+        let dfa = dfa(&["a\\b", "a\\B[a-z]", "^b$", "[a-z]\\b[0-9]"]);
+        assert_eq!(dfa.shortest_string(Some(0)), Some(b"a".to_vec()));
+        assert_eq!(dfa.shortest_string(Some(1)), Some(b"aa".to_vec()));
+        assert_eq!(dfa.count_strings(Some(1), 2), Some(26));
+        assert_eq!(dfa.count_strings(Some(2), 1), Some(1));
+        assert!(dfa.is_language_empty(Some(3)));
+        assert!(self::dfa(&["a\\B"]).is_language_empty(None));
+        assert!(self::dfa(&["(a\\b)+"]).is_language_finite(None));
+    }
+
+    #[test]
+    fn shortest_string_per_token() {
+        let dfa = dfa(&["(a|b)+(0|1)", "c+"]);
+        assert_eq!(dfa.shortest_string(Some(0)), Some(b"a0".to_vec()));
+        assert_eq!(dfa.shortest_string(Some(1)), Some(b"c".to_vec()));
+        assert_eq!(dfa.shortest_string(None), Some(b"c".to_vec()));
+        assert_eq!(dfa.shortest_string(Some(2)), None);
+    }
+}
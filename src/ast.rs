@@ -0,0 +1,185 @@
+use {
+    crate::nfa::{Assertion, ByteRange, CharRange},
+    std::fmt,
+};
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Ast {
+    pub kind: AstKind,
+    pub span: Span,
+}
+
+impl Ast {
+    pub fn new(kind: AstKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+
+    fn fmt_with_precedence(&self, f: &mut fmt::Formatter, precedence: u32) -> fmt::Result {
+        if self.precedence() < precedence {
+            write!(f, "({})", self)
+        } else {
+            write!(f, "{}", self)
+        }
+    }
+
+    fn precedence(&self) -> u32 {
+        match &self.kind {
+            AstKind::TrailingContext(..) => 0,
+            AstKind::Alternate(_) => 1,
+            AstKind::Concatenate(_) => 2,
+            AstKind::String(_) | AstKind::Repeat(..) => 3,
+            _ => 4,
+        }
+    }
+}
+
+impl fmt::Display for Ast {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.kind {
+            AstKind::Empty => write!(f, "()"),
+            AstKind::Literal(ch) => fmt_char(f, *ch, "$()*+/?[]\\^{|"),
+            AstKind::String(string) => {
+                for ch in string.chars() {
+                    fmt_char(f, ch, "$()*+/?[]\\^{|")?;
+                }
+                Ok(())
+            }
+            AstKind::Byte(byte) => write!(f, "\\x{:02X}", byte),
+            AstKind::Assertion(assertion) => f.write_str(match assertion {
+                Assertion::StartOfInput => "\\A",
+                Assertion::EndOfInput => "\\z",
+                Assertion::StartOfLine => "^",
+                Assertion::EndOfLine => "$",
+                Assertion::WordBoundary => "\\b",
+                Assertion::NotWordBoundary => "\\B",
+            }),
+            AstKind::Class(class) => write!(f, "{}", class),
+            AstKind::Concatenate(asts) => {
+                for ast in asts {
+                    ast.fmt_with_precedence(f, 3)?;
+                }
+                Ok(())
+            }
+            AstKind::Alternate(asts) => {
+                for (index, ast) in asts.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, "|")?;
+                    }
+                    ast.fmt_with_precedence(f, 2)?;
+                }
+                Ok(())
+            }
+            AstKind::Repeat(ast, repetition) => {
+                ast.fmt_with_precedence(f, 4)?;
+                write!(f, "{}", repetition)
+            }
+            AstKind::Group(ast) => write!(f, "({})", ast),
+            AstKind::Reference(name) => write!(f, "{{{}}}", name),
+            AstKind::TrailingContext(ast, trailing_context) => {
+                ast.fmt_with_precedence(f, 1)?;
+                write!(f, "/")?;
+                trailing_context.fmt_with_precedence(f, 1)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum AstKind {
+    Empty,
+    Literal(char),
+    String(String),
+    Byte(u8),
+    Assertion(Assertion),
+    Class(Class),
+    Concatenate(Vec<Ast>),
+    Alternate(Vec<Ast>),
+    Repeat(Box<Ast>, Repetition),
+    Group(Box<Ast>),
+    Reference(String),
+    TrailingContext(Box<Ast>, Box<Ast>),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Class {
+    Bytes(Vec<ByteRange>),
+    Chars(Vec<CharRange>),
+}
+
+impl fmt::Display for Class {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[")?;
+        match self {
+            Class::Bytes(byte_ranges) => {
+                for byte_range in byte_ranges {
+                    fmt_class_byte(f, byte_range.start)?;
+                    if byte_range.start < byte_range.end {
+                        write!(f, "-")?;
+                        fmt_class_byte(f, byte_range.end)?;
+                    }
+                }
+            }
+            Class::Chars(char_ranges) => {
+                for char_range in char_ranges {
+                    fmt_char(f, char_range.start, "]-^\\")?;
+                    if char_range.start < char_range.end {
+                        write!(f, "-")?;
+                        fmt_char(f, char_range.end, "]-^\\")?;
+                    }
+                }
+            }
+        }
+        write!(f, "]")
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Repetition {
+    ZeroOrOne,
+    ZeroOrMore,
+    OneOrMore,
+}
+
+impl fmt::Display for Repetition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Repetition::ZeroOrOne => "?",
+            Repetition::ZeroOrMore => "*",
+            Repetition::OneOrMore => "+",
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start, other.end)
+    }
+}
+
+fn fmt_char(f: &mut fmt::Formatter, ch: char, metacharacters: &str) -> fmt::Result {
+    if ch.is_ascii() && !ch.is_ascii_graphic() && ch != ' ' {
+        write!(f, "\\x{:02X}", ch as u8)
+    } else if metacharacters.contains(ch) {
+        write!(f, "\\{}", ch)
+    } else {
+        write!(f, "{}", ch)
+    }
+}
+
+fn fmt_class_byte(f: &mut fmt::Formatter, byte: u8) -> fmt::Result {
+    if byte.is_ascii() {
+        fmt_char(f, byte as char, "]-^\\")
+    } else {
+        write!(f, "\\x{:02X}", byte)
+    }
+}
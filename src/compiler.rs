@@ -0,0 +1,76 @@
+use crate::{
+    ast::{Ast, AstKind, Class, Repetition},
+    nfa::{Builder, Nfa},
+    token::TokenTable,
+};
+
+#[derive(Debug, Default)]
+pub struct Compiler {
+    nfa: Builder,
+}
+
+impl Compiler {
+    pub fn compile(&mut self, ast: &Ast, token: usize) {
+        match &ast.kind {
+            AstKind::TrailingContext(ast, trailing_context) => {
+                self.compile_ast(trailing_context);
+                self.nfa.accept_trailing_context(token);
+                self.compile_ast(ast);
+                self.compile_ast(trailing_context);
+                self.nfa.trailing_context(token);
+            }
+            _ => self.compile_ast(ast),
+        }
+        self.nfa.accept(token);
+    }
+
+    pub fn literal(&mut self, bytes: &[u8], token: usize) {
+        self.nfa.literal(bytes, token);
+    }
+
+    pub fn tokens_mut(&mut self) -> &mut TokenTable {
+        self.nfa.tokens_mut()
+    }
+
+    pub fn build(self) -> Nfa {
+        self.nfa.build()
+    }
+
+    fn compile_ast(&mut self, ast: &Ast) {
+        match &ast.kind {
+            AstKind::Empty => self.nfa.empty(),
+            AstKind::Literal(ch) => self.nfa.char(*ch),
+            AstKind::String(string) => self.nfa.string(string),
+            AstKind::Byte(byte) => self.nfa.byte(*byte),
+            AstKind::Assertion(assertion) => self.nfa.assertion(*assertion),
+            AstKind::Class(Class::Bytes(byte_ranges)) => self.nfa.byte_class(byte_ranges),
+            AstKind::Class(Class::Chars(char_ranges)) => self.nfa.char_class(char_ranges),
+            AstKind::Concatenate(asts) if asts.is_empty() => self.nfa.empty(),
+            AstKind::Concatenate(asts) => self.compile_asts(asts, Builder::concatenate),
+            AstKind::Alternate(asts) if asts.is_empty() => self.nfa.byte_class(&[]),
+            AstKind::Alternate(asts) => self.compile_asts(asts, Builder::alternate),
+            AstKind::Repeat(ast, repetition) => {
+                self.compile_ast(ast);
+                match repetition {
+                    Repetition::ZeroOrOne => self.nfa.zero_or_one(),
+                    Repetition::ZeroOrMore => self.nfa.zero_or_more(),
+                    Repetition::OneOrMore => self.nfa.one_or_more(),
+                }
+            }
+            AstKind::Group(ast) => self.compile_ast(ast),
+            AstKind::Reference(name) => unreachable!("reference {{{}}} was not expanded", name),
+            AstKind::TrailingContext(..) => {
+                unreachable!("trailing context is only allowed at the top level")
+            }
+        }
+    }
+
+    fn compile_asts(&mut self, asts: &[Ast], combine: fn(&mut Builder)) {
+        let (first, rest) = asts.split_first().unwrap();
+        self.compile_ast(first);
+        for ast in rest {
+            self.compile_ast(ast);
+            combine(&mut self.nfa);
+        }
+    }
+}
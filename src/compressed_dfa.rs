@@ -0,0 +1,403 @@
+use {
+    crate::{
+        dfa::{self, Dfa, StateId, StateIdRepr},
+        nfa::{ByteClass, BYTE_CLASSES, BYTE_CLASS_COUNT},
+        token::TokenTable,
+    },
+    std::{collections::HashMap, mem},
+};
+
+/// A DFA stored in flex-style comb tables. Each state has a base offset into `next` and `check`
+/// and an optional default state, and stores only the transitions in which it differs from its
+/// default. State ids are stored as `S`, like in the `Dfa` it was built from.
+#[derive(Clone, Debug)]
+pub struct CompressedDfa<S = usize> {
+    states: Vec<[Option<usize>; BYTE_CLASS_COUNT]>,
+    matched_token_sets: Vec<[Box<[usize]>; BYTE_CLASS_COUNT]>,
+    marker_token_sets: Vec<Box<[usize]>>,
+    start_state_ids: [StateId; BYTE_CLASS_COUNT],
+    bases: Vec<usize>,
+    defaults: Vec<S>,
+    next: Vec<S>,
+    check: Vec<S>,
+    token_count: usize,
+    tokens: TokenTable,
+    uncompressed_memory_usage: usize,
+}
+
+impl<S: StateIdRepr> CompressedDfa<S> {
+    pub fn new(dfa: &Dfa<S>) -> Self {
+        let rows = dfa
+            .states()
+            .map(|(_, state)| {
+                state
+                    .transitions()
+                    .map(|transition| transition.next_state_id)
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let mut compressed_dfa = Self {
+            states: dfa
+                .states()
+                .map(|(_, state)| *state.matched_token_by_class())
+                .collect(),
+            matched_token_sets: dfa
+                .states()
+                .map(|(_, state)| state.matched_tokens_by_class().clone())
+                .collect(),
+            marker_token_sets: dfa
+                .states()
+                .map(|(_, state)| state.marker_tokens().into())
+                .collect(),
+            start_state_ids: BYTE_CLASSES.map(|previous| dfa.start_state_id_for(previous)),
+            bases: Vec::with_capacity(rows.len()),
+            defaults: Vec::with_capacity(rows.len()),
+            next: Vec::new(),
+            check: Vec::new(),
+            token_count: dfa.token_count(),
+            tokens: dfa.tokens().clone(),
+            uncompressed_memory_usage: dfa.memory_usage(),
+        };
+        let mut builder = Builder::default();
+        let mut entries = Vec::new();
+        for (state_id, row) in rows.iter().enumerate() {
+            let most_common_next_state_id = most_common_next_state_id(row);
+            let default = builder.choose_default(&rows, row, most_common_next_state_id);
+            entries.clear();
+            entries.extend(row.iter().enumerate().filter_map(|(byte, &next_state_id)| {
+                let default_next_state_id =
+                    default.map_or(dfa::dead_state_id(), |default| rows[default][byte]);
+                if next_state_id == default_next_state_id {
+                    None
+                } else {
+                    Some((byte, next_state_id))
+                }
+            }));
+            let base = compressed_dfa.find_base(&builder, &entries);
+            compressed_dfa.insert_entries(&mut builder, state_id, base, &entries);
+            compressed_dfa.bases.push(base);
+            compressed_dfa
+                .defaults
+                .push(S::from_usize(default.unwrap_or_else(dfa::dead_state_id)));
+            if default.is_none() && state_id != dfa::dead_state_id() {
+                builder.add_candidate(state_id, most_common_next_state_id);
+            }
+        }
+        compressed_dfa
+    }
+
+    pub fn state_count(&self) -> usize {
+        self.states.len()
+    }
+
+    pub fn token_count(&self) -> usize {
+        self.token_count
+    }
+
+    pub fn tokens(&self) -> &TokenTable {
+        &self.tokens
+    }
+
+    pub fn matched_token(&self, state_id: StateId) -> Option<usize> {
+        self.states[state_id][ByteClass::Boundary as usize]
+    }
+
+    pub fn matched_token_before(&self, state_id: StateId, next: Option<u8>) -> Option<usize> {
+        self.states[state_id][ByteClass::of(next) as usize]
+    }
+
+    pub fn start_state_id_after(&self, previous: Option<u8>) -> StateId {
+        self.start_state_ids[ByteClass::of(previous) as usize]
+    }
+
+    pub fn matched_tokens(&self, state_id: StateId) -> &[usize] {
+        &self.matched_token_sets[state_id][ByteClass::Boundary as usize]
+    }
+
+    pub fn matched_tokens_before(&self, state_id: StateId, next: Option<u8>) -> &[usize] {
+        &self.matched_token_sets[state_id][ByteClass::of(next) as usize]
+    }
+
+    pub fn marker_tokens(&self, state_id: StateId) -> &[usize] {
+        &self.marker_token_sets[state_id]
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        self.states.len() * mem::size_of::<[Option<usize>; BYTE_CLASS_COUNT]>()
+            + self
+                .matched_token_sets
+                .iter()
+                .flatten()
+                .map(|matched_tokens| mem::size_of_val(&**matched_tokens))
+                .sum::<usize>()
+            + self.matched_token_sets.len() * mem::size_of::<[Box<[usize]>; BYTE_CLASS_COUNT]>()
+            + self
+                .marker_token_sets
+                .iter()
+                .map(|marker_tokens| mem::size_of_val(&**marker_tokens))
+                .sum::<usize>()
+            + self.marker_token_sets.len() * mem::size_of::<Box<[usize]>>()
+            + self.bases.len() * mem::size_of::<usize>()
+            + (self.defaults.len() + self.next.len() + self.check.len()) * mem::size_of::<S>()
+    }
+
+    pub fn compression_ratio(&self) -> f64 {
+        self.uncompressed_memory_usage as f64 / self.memory_usage() as f64
+    }
+
+    pub fn next_state_id(&self, mut state_id: StateId, byte: u8) -> StateId {
+        loop {
+            let offset = self.bases[state_id] + byte as usize;
+            if self
+                .check
+                .get(offset)
+                .is_some_and(|&owner| owner.to_usize() == state_id)
+            {
+                return self.next[offset].to_usize();
+            }
+            state_id = self.defaults[state_id].to_usize();
+            if state_id == dfa::dead_state_id() {
+                return state_id;
+            }
+        }
+    }
+
+    pub fn longest_match<B>(&self, bytes: B) -> Option<(usize, B)>
+    where
+        B: Clone + Iterator<Item = u8>,
+    {
+        self.longest_match_after(None, bytes)
+    }
+
+    pub fn longest_match_after<B>(&self, previous: Option<u8>, mut bytes: B) -> Option<(usize, B)>
+    where
+        B: Clone + Iterator<Item = u8>,
+    {
+        let mut state_id = self.start_state_id_after(previous);
+        let mut longest_match = self
+            .matched_token_before(state_id, bytes.clone().next())
+            .map(|token| (token, bytes.clone()));
+        while let Some(byte) = bytes.next() {
+            state_id = self.next_state_id(state_id, byte);
+            if state_id == dfa::dead_state_id() {
+                break;
+            }
+            let matched_token_by_class = &self.states[state_id];
+            let matched_token = if matched_token_by_class[1..]
+                .iter()
+                .all(|&matched_token| matched_token == matched_token_by_class[0])
+            {
+                matched_token_by_class[0]
+            } else {
+                matched_token_by_class[ByteClass::of(bytes.clone().next()) as usize]
+            };
+            if let Some(token) = matched_token {
+                longest_match = Some((token, bytes.clone()));
+            }
+        }
+        longest_match
+    }
+
+    fn find_base(&self, builder: &Builder, entries: &[(usize, StateId)]) -> usize {
+        let Some(&(first_byte, _)) = entries.first() else {
+            return 0;
+        };
+        (builder.first_free.saturating_sub(first_byte)..)
+            .find(|&base| {
+                entries.iter().all(|&(byte, _)| {
+                    self.check
+                        .get(base + byte)
+                        .is_none_or(|&owner| owner.to_usize() == EMPTY)
+                })
+            })
+            .unwrap()
+    }
+
+    fn insert_entries(
+        &mut self,
+        builder: &mut Builder,
+        state_id: StateId,
+        base: usize,
+        entries: &[(usize, StateId)],
+    ) {
+        if let Some(&(byte, _)) = entries.last() {
+            let len = (base + byte + 1).max(self.check.len());
+            self.next.resize(len, S::from_usize(dfa::dead_state_id()));
+            self.check.resize(len, S::from_usize(EMPTY));
+        }
+        for &(byte, next_state_id) in entries {
+            self.next[base + byte] = S::from_usize(next_state_id);
+            self.check[base + byte] = S::from_usize(state_id);
+        }
+        while self
+            .check
+            .get(builder.first_free)
+            .is_some_and(|&owner| owner.to_usize() != EMPTY)
+        {
+            builder.first_free += 1;
+        }
+    }
+}
+
+/// The dead state never stores any entries, since all its transitions go to itself, so its id
+/// doubles as the marker for unused slots in `check`.
+const EMPTY: StateId = 0;
+
+/// The most recent candidates that are compared against each row when looking for a default.
+const MAX_CANDIDATE_COUNT: usize = 32;
+
+#[derive(Debug, Default)]
+struct Builder {
+    candidates: HashMap<StateId, Vec<StateId>>,
+    first_free: usize,
+}
+
+impl Builder {
+    /// Looks for a default among the states whose most common next state is the same as that of
+    /// `row`, since a row can only share most of its transitions with such a state.
+    fn choose_default(
+        &self,
+        rows: &[Vec<StateId>],
+        row: &[StateId],
+        most_common_next_state_id: StateId,
+    ) -> Option<StateId> {
+        let candidates = self.candidates.get(&most_common_next_state_id)?;
+        let mut best_default = None;
+        let mut best_cost = row
+            .iter()
+            .filter(|&&next_state_id| next_state_id != dfa::dead_state_id())
+            .count();
+        for &default in candidates.iter().rev().take(MAX_CANDIDATE_COUNT) {
+            let cost = row
+                .iter()
+                .zip(&rows[default])
+                .filter(|(next_state_id, default_next_state_id)| {
+                    next_state_id != default_next_state_id
+                })
+                .count();
+            if cost < best_cost {
+                best_default = Some(default);
+                best_cost = cost;
+            }
+        }
+        best_default
+    }
+
+    fn add_candidate(&mut self, state_id: StateId, most_common_next_state_id: StateId) {
+        self.candidates
+            .entry(most_common_next_state_id)
+            .or_default()
+            .push(state_id);
+    }
+}
+
+fn most_common_next_state_id(row: &[StateId]) -> StateId {
+    let mut next_state_ids = row.to_vec();
+    next_state_ids.sort_unstable();
+    next_state_ids
+        .chunk_by(|next_state_id_0, next_state_id_1| next_state_id_0 == next_state_id_1)
+        .max_by_key(|run| (run.len(), std::cmp::Reverse(run[0])))
+        .map_or(dfa::dead_state_id(), |run| run[0])
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{determinizer::Determinizer, parser::Parser},
+    };
+
+    fn dfa(patterns: &[&str]) -> Dfa {
+        let mut parser = Parser::new();
+        for (token, pattern) in patterns.iter().enumerate() {
+            parser.parse(pattern, token).unwrap();
+        }
+        Determinizer::new(&parser.build()).determinize()
+    }
+
+    #[test]
+    fn transitions_match_uncompressed_dfa() {
+        let dfa = dfa(&["[a-z_][a-z0-9_]*", "[0-9]+", " +", "==?"]);
+        let compressed_dfa = CompressedDfa::new(&dfa);
+        assert_eq!(compressed_dfa.state_count(), dfa.state_count());
+        for (state_id, state) in dfa.states() {
+            for next in [None, Some(b'\n'), Some(b'a'), Some(b' ')] {
+                assert_eq!(
+                    compressed_dfa.matched_token_before(state_id, next),
+                    state.matched_token_before(next)
+                );
+                assert_eq!(
+                    compressed_dfa.matched_tokens_before(state_id, next),
+                    state.matched_tokens_before(next)
+                );
+            }
+            for transition in state.transitions() {
+                assert_eq!(
+                    compressed_dfa.next_state_id(state_id, transition.byte),
+                    transition.next_state_id
+                );
+            }
+        }
+        assert!(compressed_dfa.compression_ratio() > 1.0);
+    }
+
+    #[test]
+    fn longest_match_matches_uncompressed_dfa() {
+        let dfa = dfa(&["[a-z]+", "[0-9]+", "=="]);
+        let compressed_dfa = CompressedDfa::new(&dfa);
+        for haystack in [&b"iffy"[..], b"if(", b"123a", b"==a", b"?"] {
+            let expected = dfa
+                .longest_match(haystack.iter().cloned())
+                .map(|(token, rest)| (token, rest.len()));
+            let actual = compressed_dfa
+                .longest_match(haystack.iter().cloned())
+                .map(|(token, rest)| (token, rest.len()));
+            assert_eq!(actual, expected);
+        }
+    }
+
+    #[test]
+    fn stores_state_ids_with_the_width_of_the_source_dfa() {
+        let dfa = dfa(&["[a-z_][a-z0-9_]*", "[0-9]+", "==?"]);
+        let dfa = dfa.to_repr::<u16>().unwrap();
+        let compressed_dfa = CompressedDfa::new(&dfa);
+        assert!(compressed_dfa.compression_ratio() > 1.0);
+        for (state_id, state) in dfa.states() {
+            for transition in state.transitions() {
+                assert_eq!(
+                    compressed_dfa.next_state_id(state_id, transition.byte),
+                    transition.next_state_id
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn longest_match_checks_assertions() {
+        let dfa = dfa(&["\\bif\\b", "[a-z]+$", "[0-9]+"]);
+        let compressed_dfa = CompressedDfa::new(&dfa);
+        for previous in [None, Some(b'x'), Some(b' '), Some(b'\n')] {
+            for haystack in [&b"if"[..], b"iffy", b"if(", b"ab\ncd", b"ab!", b"12a"] {
+                let expected = dfa
+                    .longest_match_after(previous, haystack.iter().cloned())
+                    .map(|(token, rest)| (token, rest.len()));
+                let actual = compressed_dfa
+                    .longest_match_after(previous, haystack.iter().cloned())
+                    .map(|(token, rest)| (token, rest.len()));
+                assert_eq!(actual, expected);
+            }
+        }
+        assert_eq!(
+            compressed_dfa
+                .longest_match_after(Some(b'x'), b"if ".iter().cloned())
+                .map(|(token, _)| token),
+            None
+        );
+        assert_eq!(
+            compressed_dfa
+                .longest_match(b"ab!".iter().cloned())
+                .map(|(token, _)| token),
+            None
+        );
+    }
+}
@@ -1,10 +1,10 @@
 use {
     crate::{
         dfa::{self, Dfa, StateId},
-        nfa::{self, Nfa},
+        nfa::{self, ByteClass, Nfa, BYTE_CLASSES},
         sparse_set::SparseSet,
     },
-    std::{collections::HashMap, mem, rc::Rc},
+    std::{collections::HashMap, error, fmt, mem, rc::Rc},
 };
 
 pub struct Determinizer<'a> {
@@ -26,7 +26,26 @@ impl<'a> Determinizer<'a> {
         }
     }
 
-    pub fn determinize(mut self) -> Dfa {
+    pub fn anchored(mut self, anchored: bool) -> Self {
+        self.inner.anchored = anchored;
+        self
+    }
+
+    pub fn match_kind(mut self, match_kind: MatchKind) -> Self {
+        self.inner.match_kind = match_kind;
+        self
+    }
+
+    pub fn state_limit(mut self, state_limit: Option<usize>) -> Self {
+        self.inner.state_limit = state_limit;
+        self
+    }
+
+    pub fn determinize(self) -> Dfa {
+        self.try_determinize().unwrap()
+    }
+
+    pub fn try_determinize(mut self) -> Result<Dfa, StateLimitExceeded> {
         self.inner.determinize(
             &mut self.nfa_state_id_set,
             &mut self.nfa_state_id_stack,
@@ -38,9 +57,18 @@ impl<'a> Determinizer<'a> {
 
 struct DeterminizerInner<'a> {
     nfa: &'a Nfa,
+    has_assertions: bool,
+    anchored: bool,
+    match_kind: MatchKind,
+    state_limit: Option<usize>,
     dfa: Dfa,
     states: Vec<Rc<State>>,
     state_ids_by_state: HashMap<Rc<State>, StateId>,
+    group_ends: Vec<usize>,
+    lookahead_key: Option<(StateId, ByteClass)>,
+    lookahead_nfa_state_id_set: SparseSet,
+    lookahead_nfa_state_ids: Vec<nfa::StateId>,
+    lookahead_group_ends: Vec<usize>,
 }
 
 impl<'a> DeterminizerInner<'a> {
@@ -48,11 +76,22 @@ impl<'a> DeterminizerInner<'a> {
         let dead_state = Rc::new(State::default());
         let mut state_ids_by_state = HashMap::new();
         state_ids_by_state.insert(dead_state.clone(), dfa::dead_state_id());
+        let mut dfa = Dfa::new();
+        *dfa.tokens_mut() = nfa.tokens().clone();
         Self {
             nfa,
-            dfa: Dfa::new(),
+            has_assertions: nfa.has_assertions(),
+            anchored: true,
+            match_kind: MatchKind::default(),
+            state_limit: None,
+            dfa,
             states: vec![dead_state],
             state_ids_by_state,
+            group_ends: Vec::new(),
+            lookahead_key: None,
+            lookahead_nfa_state_id_set: SparseSet::new(nfa.state_count()),
+            lookahead_nfa_state_ids: Vec::new(),
+            lookahead_group_ends: Vec::new(),
         }
     }
 
@@ -62,10 +101,33 @@ impl<'a> DeterminizerInner<'a> {
         nfa_state_id_stack: &mut Vec<nfa::StateId>,
         nfa_state_ids: &mut Vec<nfa::StateId>,
         matched_tokens: &mut Vec<usize>,
-    ) -> Dfa {
+    ) -> Result<Dfa, StateLimitExceeded> {
         let mut state_id_stack =
-            vec![self.create_start_state(nfa_state_id_set, nfa_state_id_stack)];
+            vec![self.create_start_state(nfa_state_id_set, nfa_state_id_stack, matched_tokens)];
+        if self.has_assertions {
+            for &previous in &BYTE_CLASSES[1..] {
+                self.start_nfa_state_id_set(previous, nfa_state_id_set, nfa_state_id_stack);
+                let (start_state_id, is_new) = self.get_or_create_state(
+                    previous,
+                    false,
+                    nfa_state_id_set,
+                    nfa_state_id_stack,
+                    nfa_state_ids,
+                    matched_tokens,
+                );
+                self.dfa.set_start_state_id(previous, start_state_id);
+                if is_new {
+                    state_id_stack.push(start_state_id);
+                }
+            }
+        }
         while let Some(state_id) = state_id_stack.pop() {
+            if self.match_kind == MatchKind::Shortest
+                && !self.dfa.is_start_state(state_id)
+                && self.states[state_id].matched_token.is_some()
+            {
+                continue;
+            }
             for byte in 0..=u8::MAX {
                 let (next_state_id, is_new) = self.get_or_create_next_state(
                     state_id,
@@ -77,38 +139,72 @@ impl<'a> DeterminizerInner<'a> {
                 );
                 self.dfa.add_transition(state_id, byte, next_state_id);
                 if is_new {
+                    if self
+                        .state_limit
+                        .is_some_and(|state_limit| self.dfa.state_count() > state_limit)
+                    {
+                        return Err(StateLimitExceeded);
+                    }
                     state_id_stack.push(next_state_id);
                 }
             }
         }
-        self.dfa
+        Ok(self.dfa)
     }
 
     fn create_start_state(
         &mut self,
         nfa_state_id_set: &mut SparseSet,
         nfa_state_id_stack: &mut Vec<nfa::StateId>,
+        matched_tokens: &mut Vec<usize>,
     ) -> StateId {
-        let start_state_id = self.dfa.add_state(None);
-        self.start_nfa_state_id_set(nfa_state_id_set, nfa_state_id_stack);
+        self.start_nfa_state_id_set(ByteClass::Boundary, nfa_state_id_set, nfa_state_id_stack);
+        let mut nfa_state_ids = Vec::new();
+        let mut group_ends = mem::take(&mut self.group_ends);
+        self.collect_nfa_state_ids(nfa_state_id_set, &mut nfa_state_ids, &mut group_ends);
+        self.matched_tokens(&nfa_state_ids, matched_tokens);
+        let matched_token = matched_tokens.first().cloned();
+        let start_state_id = self.dfa.add_state_with_matched_tokens(matched_tokens);
+        self.dfa
+            .set_marker_tokens(start_state_id, &self.marker_tokens(&nfa_state_ids));
         let start_state = Rc::new(State {
-            matched_token: None,
-            nfa_state_ids: nfa_state_id_set.iter().collect::<Vec<_>>(),
+            is_matched: false,
+            nfa_state_ids,
+            group_ends,
+            previous: ByteClass::Boundary,
+            matched_token,
         });
         self.states.push(start_state.clone());
         self.state_ids_by_state.insert(start_state, start_state_id);
+        if self.has_assertions {
+            self.set_lookahead_matched_tokens(start_state_id, nfa_state_id_stack, matched_tokens);
+        }
         start_state_id
     }
 
     fn start_nfa_state_id_set(
         &mut self,
+        previous: ByteClass,
         nfa_state_id_set: &mut SparseSet,
         nfa_state_id_stack: &mut Vec<nfa::StateId>,
     ) {
         nfa_state_id_set.clear();
+        self.add_start_nfa_state_ids(previous, nfa_state_id_set, nfa_state_id_stack);
+        self.group_ends.clear();
+        self.group_ends.push(nfa_state_id_set.len());
+    }
+
+    fn add_start_nfa_state_ids(
+        &self,
+        previous: ByteClass,
+        nfa_state_id_set: &mut SparseSet,
+        nfa_state_id_stack: &mut Vec<nfa::StateId>,
+    ) {
         for &fragment in self.nfa.fragments() {
-            self.nfa.empty_closure(
+            self.nfa.closure(
                 fragment.start_state_id,
+                previous,
+                None,
                 nfa_state_id_set,
                 nfa_state_id_stack,
             );
@@ -124,8 +220,16 @@ impl<'a> DeterminizerInner<'a> {
         nfa_state_ids: &mut Vec<nfa::StateId>,
         matched_tokens: &mut Vec<usize>,
     ) -> (StateId, bool) {
-        self.next_nfa_state_id_set(state_id, byte, nfa_state_id_set, nfa_state_id_stack);
-        self.get_or_create_state(nfa_state_id_set, nfa_state_ids, matched_tokens)
+        let is_matched =
+            self.next_nfa_state_id_set(state_id, byte, nfa_state_id_set, nfa_state_id_stack);
+        self.get_or_create_state(
+            self.byte_class(Some(byte)),
+            is_matched,
+            nfa_state_id_set,
+            nfa_state_id_stack,
+            nfa_state_ids,
+            matched_tokens,
+        )
     }
 
     fn next_nfa_state_id_set(
@@ -134,63 +238,370 @@ impl<'a> DeterminizerInner<'a> {
         byte: u8,
         nfa_state_id_set: &mut SparseSet,
         nfa_state_id_stack: &mut Vec<nfa::StateId>,
-    ) {
+    ) -> bool {
+        let previous = self.byte_class(Some(byte));
+        if self.has_assertions {
+            self.lookahead_nfa_state_ids(state_id, previous, nfa_state_id_stack);
+        }
+        let stops_at_match = self.stops_at_match();
         let state = &self.states[state_id];
+        let (nfa_state_ids, group_ends) = if self.has_assertions {
+            (&self.lookahead_nfa_state_ids, &self.lookahead_group_ends)
+        } else {
+            (&state.nfa_state_ids, &state.group_ends)
+        };
+        let mut is_matched = state.is_matched;
+        self.group_ends.clear();
         nfa_state_id_set.clear();
-        for &nfa_state_id in &state.nfa_state_ids {
-            for transition in &self.nfa.state(nfa_state_id).transitions {
-                if transition
-                    .byte_range
-                    .as_ref()
-                    .map_or(false, |byte_range| byte_range.contains(byte))
+        let mut group_start = 0;
+        for &group_end in group_ends {
+            let group = &nfa_state_ids[group_start..group_end];
+            group_start = group_end;
+            let is_group_matched = group
+                .iter()
+                .any(|&nfa_state_id| self.nfa.state(nfa_state_id).matched_token.is_some());
+            for &nfa_state_id in group {
+                if self.match_kind == MatchKind::LeftmostFirst
+                    && self.nfa.state(nfa_state_id).matched_token.is_some()
                 {
-                    self.nfa.empty_closure(
-                        transition.next_state_id,
-                        nfa_state_id_set,
-                        nfa_state_id_stack,
-                    );
+                    break;
+                }
+                for transition in &self.nfa.state(nfa_state_id).transitions {
+                    if transition
+                        .byte_range
+                        .as_ref()
+                        .is_some_and(|byte_range| byte_range.contains(byte))
+                    {
+                        self.nfa.closure(
+                            transition.next_state_id,
+                            previous,
+                            None,
+                            nfa_state_id_set,
+                            nfa_state_id_stack,
+                        );
+                    }
                 }
             }
+            self.group_ends.push(nfa_state_id_set.len());
+            if stops_at_match && is_group_matched {
+                is_matched = true;
+                break;
+            }
         }
+        if !self.anchored && !is_matched {
+            self.add_start_nfa_state_ids(previous, nfa_state_id_set, nfa_state_id_stack);
+            self.group_ends.push(nfa_state_id_set.len());
+        }
+        is_matched
     }
 
     fn get_or_create_state(
         &mut self,
+        previous: ByteClass,
+        is_matched: bool,
         nfa_state_id_set: &SparseSet,
+        nfa_state_id_stack: &mut Vec<nfa::StateId>,
         nfa_state_ids: &mut Vec<StateId>,
         matched_tokens: &mut Vec<usize>,
     ) -> (StateId, bool) {
-        nfa_state_ids.clear();
-        nfa_state_ids.extend(&*nfa_state_id_set);
-        matched_tokens.clear();
-        matched_tokens.extend(nfa_state_id_set.iter().filter_map({
-            let nfa = &self.nfa;
-            move |nfa_state_id| nfa.state(nfa_state_id).matched_token
-        }));
-        assert!(matched_tokens.len() <= 1);
+        if nfa_state_id_set.is_empty() {
+            return (dfa::dead_state_id(), false);
+        }
+        let mut group_ends = mem::take(&mut self.group_ends);
+        self.collect_nfa_state_ids(nfa_state_id_set, nfa_state_ids, &mut group_ends);
+        self.matched_tokens(nfa_state_ids, matched_tokens);
         let matched_token = matched_tokens.first().cloned();
         let state = State {
-            nfa_state_ids: mem::replace(nfa_state_ids, Vec::new()),
+            is_matched,
+            nfa_state_ids: mem::take(nfa_state_ids),
+            group_ends,
+            previous,
             matched_token,
         };
         match self.state_ids_by_state.get(&state) {
             Some(&state_id) => {
                 *nfa_state_ids = state.nfa_state_ids;
+                self.group_ends = state.group_ends;
                 (state_id, false)
             }
             None => {
                 let state = Rc::new(state);
-                let state_id = self.dfa.add_state(matched_token);
+                let state_id = self.dfa.add_state_with_matched_tokens(matched_tokens);
+                self.dfa
+                    .set_marker_tokens(state_id, &self.marker_tokens(&state.nfa_state_ids));
                 self.states.push(state.clone());
                 self.state_ids_by_state.insert(state, state_id);
+                if self.has_assertions {
+                    self.set_lookahead_matched_tokens(state_id, nfa_state_id_stack, matched_tokens);
+                }
                 (state_id, true)
             }
         }
     }
+
+    fn set_lookahead_matched_tokens(
+        &mut self,
+        state_id: StateId,
+        nfa_state_id_stack: &mut Vec<nfa::StateId>,
+        matched_tokens: &mut Vec<usize>,
+    ) {
+        for &next in &BYTE_CLASSES {
+            self.lookahead_nfa_state_ids(state_id, next, nfa_state_id_stack);
+            let lookahead_nfa_state_ids = mem::take(&mut self.lookahead_nfa_state_ids);
+            self.matched_tokens(&lookahead_nfa_state_ids, matched_tokens);
+            self.lookahead_nfa_state_ids = lookahead_nfa_state_ids;
+            self.dfa
+                .set_matched_tokens_before(state_id, next, matched_tokens);
+        }
+    }
+
+    /// Extends the NFA states of `state_id` with the assertions that hold between its previous
+    /// byte and a next byte of class `next`, keeping the groups apart. The result is cached for
+    /// the last state and class, since every byte of a class asks for the same set.
+    fn lookahead_nfa_state_ids(
+        &mut self,
+        state_id: StateId,
+        next: ByteClass,
+        nfa_state_id_stack: &mut Vec<nfa::StateId>,
+    ) {
+        if self.lookahead_key == Some((state_id, next)) {
+            return;
+        }
+        self.lookahead_key = Some((state_id, next));
+        let state = &self.states[state_id];
+        self.lookahead_nfa_state_id_set.clear();
+        self.lookahead_group_ends.clear();
+        let mut group_start = 0;
+        for &group_end in &state.group_ends {
+            for &nfa_state_id in &state.nfa_state_ids[group_start..group_end] {
+                self.nfa.closure(
+                    nfa_state_id,
+                    state.previous,
+                    Some(next),
+                    &mut self.lookahead_nfa_state_id_set,
+                    nfa_state_id_stack,
+                );
+            }
+            group_start = group_end;
+            self.lookahead_group_ends
+                .push(self.lookahead_nfa_state_id_set.len());
+        }
+        let mut lookahead_nfa_state_ids = mem::take(&mut self.lookahead_nfa_state_ids);
+        let mut lookahead_group_ends = mem::take(&mut self.lookahead_group_ends);
+        self.collect_nfa_state_ids(
+            &self.lookahead_nfa_state_id_set,
+            &mut lookahead_nfa_state_ids,
+            &mut lookahead_group_ends,
+        );
+        self.lookahead_nfa_state_ids = lookahead_nfa_state_ids;
+        self.lookahead_group_ends = lookahead_group_ends;
+    }
+
+    fn byte_class(&self, byte: Option<u8>) -> ByteClass {
+        if self.has_assertions {
+            ByteClass::of(byte)
+        } else {
+            ByteClass::Boundary
+        }
+    }
+
+    fn stops_at_match(&self) -> bool {
+        !self.anchored && self.match_kind != MatchKind::All
+    }
+
+    /// Copies the NFA states of `nfa_state_id_set` into `nfa_state_ids`, group by group, and
+    /// rewrites `group_ends` to match. For leftmost-first, each group keeps its threads in
+    /// priority order and drops those after its first match. Otherwise each group is sorted, and
+    /// groups are merged into one if they are never dropped.
+    fn collect_nfa_state_ids(
+        &self,
+        nfa_state_id_set: &SparseSet,
+        nfa_state_ids: &mut Vec<nfa::StateId>,
+        group_ends: &mut Vec<usize>,
+    ) {
+        nfa_state_ids.clear();
+        if !self.stops_at_match() {
+            group_ends.clear();
+            group_ends.push(nfa_state_id_set.len());
+        }
+        let mut nfa_state_id_iter = nfa_state_id_set.iter();
+        let mut group_start = 0;
+        for group_end in group_ends.iter_mut() {
+            let group = nfa_state_id_iter.by_ref().take(*group_end - group_start);
+            group_start = *group_end;
+            let collected_group_start = nfa_state_ids.len();
+            match self.match_kind {
+                MatchKind::LeftmostFirst => {
+                    for nfa_state_id in group {
+                        nfa_state_ids.push(nfa_state_id);
+                        if self.nfa.state(nfa_state_id).matched_token.is_some() {
+                            break;
+                        }
+                    }
+                }
+                MatchKind::LeftmostLongest | MatchKind::Shortest | MatchKind::All => {
+                    nfa_state_ids.extend(group);
+                    nfa_state_ids[collected_group_start..].sort_unstable();
+                }
+            }
+            *group_end = nfa_state_ids.len();
+        }
+        group_ends.dedup();
+        group_ends.retain(|&group_end| group_end != 0);
+    }
+
+    fn matched_tokens(&self, nfa_state_ids: &[nfa::StateId], matched_tokens: &mut Vec<usize>) {
+        matched_tokens.clear();
+        matched_tokens.extend(
+            nfa_state_ids
+                .iter()
+                .filter_map(|&nfa_state_id| self.nfa.state(nfa_state_id).matched_token),
+        );
+        let tokens = self.nfa.tokens();
+        matched_tokens.sort_unstable_by_key(|&token| (tokens.priority(token), token));
+        matched_tokens.dedup();
+    }
+
+    fn marker_tokens(&self, nfa_state_ids: &[nfa::StateId]) -> Vec<usize> {
+        let mut marker_tokens = nfa_state_ids
+            .iter()
+            .filter_map(|&nfa_state_id| self.nfa.state(nfa_state_id).marker_token)
+            .collect::<Vec<_>>();
+        marker_tokens.sort_unstable();
+        marker_tokens.dedup();
+        marker_tokens
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum MatchKind {
+    #[default]
+    LeftmostLongest,
+    LeftmostFirst,
+    Shortest,
+    All,
 }
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct StateLimitExceeded;
+
+impl fmt::Display for StateLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("DFA state limit exceeded")
+    }
+}
+
+impl error::Error for StateLimitExceeded {}
+
+/// In an unanchored search, `group_ends` splits the NFA states into groups of threads that started
+/// at the same position, earliest first. Once a group matches, later groups can no longer produce
+/// the leftmost match, so they are dropped and `is_matched` stops new threads from being started.
 #[derive(Clone, Debug, Default, Hash, Eq, PartialEq)]
 struct State {
+    is_matched: bool,
     nfa_state_ids: Vec<nfa::StateId>,
+    group_ends: Vec<usize>,
+    previous: ByteClass,
     matched_token: Option<usize>,
 }
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{parser::Parser, token::Token},
+    };
+
+    fn dfa(pattern: &str, match_kind: MatchKind) -> Dfa {
+        let mut parser = Parser::new();
+        parser.set_match_kind(match_kind);
+        parser.parse(pattern, 0).unwrap();
+        Determinizer::new(&parser.build())
+            .match_kind(match_kind)
+            .determinize()
+    }
+
+    fn longest_match_len(dfa: &Dfa, bytes: &[u8]) -> Option<usize> {
+        let (_, rest) = dfa.longest_match(bytes.iter().cloned())?;
+        Some(bytes.len() - rest.len())
+    }
+
+    #[test]
+    fn ties_are_broken_by_token_priority() {
+        let mut parser = Parser::new();
+        parser.parse("[a-z]+", 0).unwrap();
+        parser.parse("if", 1).unwrap();
+        for (token, priority) in [(0, 1), (1, 0)] {
+            parser.tokens_mut().insert(
+                token,
+                Token {
+                    name: format!("T{}", token),
+                    pattern: String::new(),
+                    priority,
+                    skip: false,
+                },
+            );
+        }
+        let dfa = Determinizer::new(&parser.build()).determinize();
+        assert_eq!(dfa.longest_match(b"if".iter().cloned()).unwrap().0, 1);
+        assert_eq!(dfa.longest_match(b"iff".iter().cloned()).unwrap().0, 0);
+    }
+
+    #[test]
+    fn leftmost_longest_prefers_the_longest_alternative() {
+        let dfa = dfa("a|ab", MatchKind::LeftmostLongest);
+        assert_eq!(longest_match_len(&dfa, b"abc"), Some(2));
+        let dfa = self::dfa("ab|a", MatchKind::LeftmostLongest);
+        assert_eq!(longest_match_len(&dfa, b"abc"), Some(2));
+    }
+
+    #[test]
+    fn leftmost_first_prefers_the_first_alternative() {
+        let dfa = dfa("a|ab", MatchKind::LeftmostFirst);
+        assert_eq!(longest_match_len(&dfa, b"abc"), Some(1));
+        let dfa = self::dfa("ab|a", MatchKind::LeftmostFirst);
+        assert_eq!(longest_match_len(&dfa, b"abc"), Some(2));
+        assert_eq!(longest_match_len(&dfa, b"ac"), Some(1));
+    }
+
+    #[test]
+    fn shortest_stops_at_the_first_accepting_state() {
+        let dfa = dfa("ab|a", MatchKind::Shortest);
+        let (_, rest) = dfa.shortest_match(b"abc".iter().cloned()).unwrap();
+        assert_eq!(rest.len(), 2);
+        assert_eq!(longest_match_len(&dfa, b"abc"), Some(1));
+    }
+
+    #[test]
+    fn unanchored_dfa_stops_starting_threads_after_a_match() {
+        let dfa = Determinizer::new(&{
+            let mut parser = Parser::new();
+            parser.parse("a", 0).unwrap();
+            parser.build()
+        })
+        .anchored(false)
+        .determinize();
+        let state_id = dfa.next_state_id(dfa::start_state_id(), b'a');
+        assert_eq!(dfa.state(state_id).matched_token(), Some(0));
+        assert_eq!(dfa.next_state_id(state_id, b'a'), dfa::dead_state_id());
+    }
+
+    #[test]
+    fn unanchored_leftmost_first_keeps_earlier_threads_first() {
+        let mut parser = Parser::new();
+        parser.parse("b|abc", 0).unwrap();
+        let dfa = Determinizer::new(&parser.build())
+            .anchored(false)
+            .match_kind(MatchKind::LeftmostFirst)
+            .determinize();
+        let mut last_match_end = None;
+        let mut state_id = dfa::start_state_id();
+        for (index, &byte) in b"abc".iter().enumerate() {
+            state_id = dfa.next_state_id(state_id, byte);
+            if dfa.state(state_id).matched_token().is_some() {
+                last_match_end = Some(index + 1);
+            }
+        }
+        assert_eq!(last_match_end, Some(3));
+    }
+}
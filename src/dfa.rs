@@ -1,20 +1,40 @@
-use std::{
-    iter::{self, Cloned, Enumerate, Zip},
-    slice::{Chunks, Iter},
+use {
+    crate::{
+        analysis,
+        minimizer::Minimizer,
+        nfa::{ByteClass, BYTE_CLASSES, BYTE_CLASS_COUNT},
+        product::{self, Operation, Product, Relation},
+        sampler::Sampler,
+        serialize::{self, Reader, Writer},
+        state_eliminator::StateEliminator,
+        strings::Strings,
+        token::{Token, TokenTable},
+    },
+    std::{
+        convert::TryFrom,
+        error::Error,
+        fmt::{self, Debug, Display, Formatter},
+        hash::Hash,
+        iter::{Cloned, Enumerate},
+        mem,
+        slice::Iter,
+    },
 };
 
 #[derive(Clone, Debug)]
-pub struct Dfa {
-    states: Vec<Option<usize>>,
-    transitions: Vec<StateId>,
+pub struct Dfa<S = usize> {
+    states: Vec<[Option<usize>; BYTE_CLASS_COUNT]>,
+    matched_token_sets: Vec<[Box<[usize]>; BYTE_CLASS_COUNT]>,
+    marker_token_sets: Vec<Box<[usize]>>,
+    needs_lookahead: Vec<bool>,
+    start_state_ids: [StateId; BYTE_CLASS_COUNT],
+    transitions: Vec<S>,
     token_count: usize,
+    tokens: TokenTable,
+    premultiplied: bool,
 }
 
-impl Dfa {
-    pub fn new() -> Self {
-        Self::default()
-    }
-
+impl<S: StateIdRepr> Dfa<S> {
     pub fn state_count(&self) -> usize {
         self.states.len()
     }
@@ -23,50 +43,625 @@ impl Dfa {
         self.token_count
     }
 
-    pub fn states(&self) -> States {
-        States {
-            iter: self
-                .states
+    pub fn tokens(&self) -> &TokenTable {
+        &self.tokens
+    }
+
+    pub fn tokens_mut(&mut self) -> &mut TokenTable {
+        &mut self.tokens
+    }
+
+    pub fn is_premultiplied(&self) -> bool {
+        self.premultiplied
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        self.states.len() * mem::size_of::<[Option<usize>; BYTE_CLASS_COUNT]>()
+            + self
+                .matched_token_sets
+                .iter()
+                .flatten()
+                .map(|matched_tokens| mem::size_of_val(&**matched_tokens))
+                .sum::<usize>()
+            + self.matched_token_sets.len() * mem::size_of::<[Box<[usize]>; BYTE_CLASS_COUNT]>()
+            + self
+                .marker_token_sets
                 .iter()
-                .cloned()
-                .zip(self.transitions.chunks(u8::MAX as usize))
-                .enumerate(),
+                .map(|marker_tokens| mem::size_of_val(&**marker_tokens))
+                .sum::<usize>()
+            + self.marker_token_sets.len() * mem::size_of::<Box<[usize]>>()
+            + self.needs_lookahead.len() * mem::size_of::<bool>()
+            + self.transitions.len() * mem::size_of::<S>()
+    }
+
+    pub fn states(&self) -> States<'_, S> {
+        States {
+            dfa: self,
+            state_id: 0,
+        }
+    }
+
+    pub fn state(&self, state_id: StateId) -> State<'_, S> {
+        let offset = state_id * ALPHABET_LEN;
+        State {
+            matched_token_by_class: &self.states[state_id],
+            matched_tokens_by_class: &self.matched_token_sets[state_id],
+            marker_tokens: &self.marker_token_sets[state_id],
+            transitions: &self.transitions[offset..offset + ALPHABET_LEN],
+            premultiplied: self.premultiplied,
+        }
+    }
+
+    pub fn start_state_id_for(&self, previous: ByteClass) -> StateId {
+        self.start_state_ids[previous as usize]
+    }
+
+    /// Returns the state to start in when `previous` is the byte before the input, or `None` at
+    /// the start of the haystack.
+    pub fn start_state_id_after(&self, previous: Option<u8>) -> StateId {
+        self.start_state_id_for(ByteClass::of(previous))
+    }
+
+    pub fn is_start_state(&self, state_id: StateId) -> bool {
+        self.start_state_ids.contains(&state_id)
+    }
+
+    pub fn next_state_id(&self, state_id: StateId, byte: u8) -> StateId {
+        self.logical_state_id(self.transitions[state_id * ALPHABET_LEN + byte as usize])
+    }
+
+    pub fn longest_match<B>(&self, bytes: B) -> Option<(usize, B)>
+    where
+        B: Clone + Iterator<Item = u8>,
+    {
+        self.longest_match_after(None, bytes)
+    }
+
+    /// Like `longest_match`, but starts in the state for `previous`, the byte before `bytes`.
+    pub fn longest_match_after<B>(&self, previous: Option<u8>, bytes: B) -> Option<(usize, B)>
+    where
+        B: Clone + Iterator<Item = u8>,
+    {
+        let state_id = self.start_state_id_after(previous);
+        if self.premultiplied {
+            self.longest_match_premultiplied(state_id, bytes)
+        } else {
+            self.longest_match_unpremultiplied(state_id, bytes)
         }
     }
 
-    pub fn longest_match<'a, B>(&self, mut bytes: B) -> Option<(usize, B)>
+    pub fn shortest_match<B>(&self, mut bytes: B) -> Option<(usize, B)>
     where
         B: Clone + Iterator<Item = u8>,
     {
-        let mut longest_match = None;
-        let mut state_id = start_state_id();
+        let mut state_id = self.start_state_id_after(None);
+        if let Some(token) = self.matched_token_at(state_id, &bytes) {
+            return Some((token, bytes));
+        }
         while let Some(byte) = bytes.next() {
-            let offset = state_id * (u8::MAX as usize + 1) + byte as usize;
-            state_id = self.transitions[offset];
+            state_id = self.next_state_id(state_id, byte);
             if state_id == dead_state_id() {
                 break;
             }
-            if let Some(token) = self.states[state_id] {
+            if let Some(token) = self.matched_token_at(state_id, &bytes) {
+                return Some((token, bytes));
+            }
+        }
+        None
+    }
+
+    fn longest_match_premultiplied<B>(&self, state_id: StateId, mut bytes: B) -> Option<(usize, B)>
+    where
+        B: Clone + Iterator<Item = u8>,
+    {
+        let mut longest_match = self
+            .matched_token_at(state_id, &bytes)
+            .map(|token| (token, bytes.clone()));
+        let mut offset = state_id << ALPHABET_SHIFT;
+        while let Some(byte) = bytes.next() {
+            let next_offset = self.transitions[offset + byte as usize].to_usize();
+            if next_offset == dead_state_id() {
+                break;
+            }
+            offset = next_offset & !MATCH_FLAG;
+            if next_offset & MATCH_FLAG != 0 {
+                if let Some(token) = self.matched_token_at(offset >> ALPHABET_SHIFT, &bytes) {
+                    longest_match = Some((token, bytes.clone()));
+                }
+            }
+        }
+        longest_match
+    }
+
+    fn longest_match_unpremultiplied<B>(
+        &self,
+        mut state_id: StateId,
+        mut bytes: B,
+    ) -> Option<(usize, B)>
+    where
+        B: Clone + Iterator<Item = u8>,
+    {
+        let mut longest_match = self
+            .matched_token_at(state_id, &bytes)
+            .map(|token| (token, bytes.clone()));
+        while let Some(byte) = bytes.next() {
+            state_id = self.transitions[state_id * ALPHABET_LEN + byte as usize].to_usize();
+            if state_id == dead_state_id() {
+                break;
+            }
+            if let Some(token) = self.matched_token_at(state_id, &bytes) {
                 longest_match = Some((token, bytes.clone()));
             }
         }
         longest_match
     }
 
+    // Only states whose matched token depends on the next byte pay for looking ahead.
+    fn matched_token_at<B>(&self, state_id: StateId, bytes: &B) -> Option<usize>
+    where
+        B: Clone + Iterator<Item = u8>,
+    {
+        let matched_token_by_class = &self.states[state_id];
+        if self.needs_lookahead[state_id] {
+            matched_token_by_class[ByteClass::of(bytes.clone().next()) as usize]
+        } else {
+            matched_token_by_class[ByteClass::Boundary as usize]
+        }
+    }
+
+    pub fn intersection<T, F>(&self, other: &Dfa<T>, resolve: F) -> Dfa
+    where
+        T: StateIdRepr,
+        F: FnMut(usize, usize) -> usize,
+    {
+        Product::new(self, other, Operation::Intersection, resolve).build()
+    }
+
+    pub fn union<T, F>(&self, other: &Dfa<T>, resolve: F) -> Dfa
+    where
+        T: StateIdRepr,
+        F: FnMut(usize, usize) -> usize,
+    {
+        Product::new(self, other, Operation::Union, resolve).build()
+    }
+
+    pub fn difference<T: StateIdRepr>(&self, other: &Dfa<T>) -> Dfa {
+        Product::new(self, other, Operation::Difference, |token, _| token).build()
+    }
+
+    pub fn is_equivalent<T: StateIdRepr>(&self, other: &Dfa<T>) -> Result<(), Vec<u8>> {
+        match product::find_counterexample(self, other, Relation::Equivalence) {
+            Some(counterexample) => Err(counterexample),
+            None => Ok(()),
+        }
+    }
+
+    pub fn is_subset_of<T: StateIdRepr>(&self, other: &Dfa<T>) -> Result<(), Vec<u8>> {
+        match product::find_counterexample(self, other, Relation::Inclusion) {
+            Some(counterexample) => Err(counterexample),
+            None => Ok(()),
+        }
+    }
+
+    pub fn is_language_empty(&self, token: Option<usize>) -> bool {
+        analysis::is_language_empty(self, token)
+    }
+
+    pub fn is_language_finite(&self, token: Option<usize>) -> bool {
+        analysis::is_language_finite(self, token)
+    }
+
+    pub fn count_strings(&self, token: Option<usize>, length: usize) -> Option<u128> {
+        analysis::count_strings(self, token, length)
+    }
+
+    pub fn shortest_string(&self, token: Option<usize>) -> Option<Vec<u8>> {
+        analysis::shortest_string(self, token)
+    }
+
+    pub fn strings(&self, token: Option<usize>, max_length: usize) -> Strings<'_, S> {
+        Strings::new(self, token, max_length)
+    }
+
+    pub fn sampler(
+        &self,
+        token: Option<usize>,
+        length: usize,
+        seed: u64,
+    ) -> Option<Sampler<'_, S>> {
+        Sampler::new(self, token, length, seed)
+    }
+
+    pub fn minimize(&self) -> Dfa {
+        Minimizer::new(self).minimize()
+    }
+
+    /// Returns `true` if a match can depend on the bytes around it, that is if the DFA has
+    /// different start states or matched tokens for different byte classes.
+    pub fn is_context_dependent(&self) -> bool {
+        self.start_state_ids
+            .iter()
+            .any(|&state_id| state_id != self.start_state_ids[0])
+            || self
+                .needs_lookahead
+                .iter()
+                .any(|&needs_lookahead| needs_lookahead)
+    }
+
+    /// Returns `None` for context dependent DFAs, since a pattern without assertions cannot
+    /// express them.
+    pub fn to_pattern(&self, token: Option<usize>) -> Option<String> {
+        if self.is_context_dependent() {
+            return None;
+        }
+        StateEliminator::new(self, token).eliminate()
+    }
+
+    /// Returns a DFA that accepts exactly the strings this DFA rejects. The dead state becomes an
+    /// accepting sink. Every accepting state of the complement matches a new token, numbered
+    /// `self.token_count()`, so it can be told apart from the tokens of this DFA.
+    pub fn complement(&self) -> Dfa {
+        let token = self.token_count;
+        let mut dfa = Dfa::new();
+        *dfa.tokens_mut() = self.tokens.clone();
+        dfa.tokens_mut().insert(
+            token,
+            Token {
+                name: format!("COMPLEMENT_{}", token),
+                pattern: String::new(),
+                priority: token,
+                skip: false,
+            },
+        );
+        for (state_id, state) in self.states().skip(1) {
+            dfa.add_state(None);
+            for (&class, matched_tokens) in BYTE_CLASSES.iter().zip(state.matched_tokens_by_class())
+            {
+                if matched_tokens.is_empty() {
+                    dfa.set_matched_tokens_before(state_id, class, &[token]);
+                }
+            }
+            dfa.set_marker_tokens(state_id, state.marker_tokens());
+        }
+        let sink_state_id = dfa.add_state(Some(token));
+        let complement_state_id = |state_id| {
+            if state_id == dead_state_id() {
+                sink_state_id
+            } else {
+                state_id
+            }
+        };
+        for (state_id, state) in self.states().skip(1) {
+            for transition in state.transitions() {
+                dfa.add_transition(
+                    state_id,
+                    transition.byte,
+                    complement_state_id(transition.next_state_id),
+                );
+            }
+        }
+        for byte in 0..=u8::MAX {
+            dfa.add_transition(sink_state_id, byte, sink_state_id);
+        }
+        for &class in &BYTE_CLASSES {
+            dfa.set_start_state_id(class, complement_state_id(self.start_state_id_for(class)));
+        }
+        dfa
+    }
+
+    pub fn matching_tokens<B>(&self, bytes: B) -> Vec<usize>
+    where
+        B: Iterator<Item = u8>,
+    {
+        let mut is_matched = vec![false; self.token_count];
+        let mut unmatched_count = self.token_count;
+        let mut state_id = self.start_state_id_after(None);
+        let mut bytes = bytes.peekable();
+        loop {
+            let class = ByteClass::of(bytes.peek().cloned());
+            for &token in &*self.matched_token_sets[state_id][class as usize] {
+                if !is_matched[token] {
+                    is_matched[token] = true;
+                    unmatched_count -= 1;
+                }
+            }
+            if unmatched_count == 0 {
+                break;
+            }
+            let byte = match bytes.next() {
+                Some(byte) => byte,
+                None => break,
+            };
+            state_id = self.next_state_id(state_id, byte);
+            if state_id == dead_state_id() {
+                break;
+            }
+        }
+        (0..self.token_count)
+            .filter(|&token| is_matched[token])
+            .collect()
+    }
+
+    /// Stores row offsets instead of logical state ids in the transition table. Fails without
+    /// changing anything if the offset of the last state does not fit in `S`.
+    pub fn premultiply(&mut self) -> Result<(), StateIdOverflow> {
+        if self.premultiplied {
+            return Ok(());
+        }
+        if !premultiplied_state_id_fits::<S>(self.states.len() - 1) {
+            return Err(StateIdOverflow);
+        }
+        for index in 0..self.transitions.len() {
+            let state_id = self.transitions[index].to_usize();
+            self.transitions[index] = S::from_usize(self.premultiplied_state_id(state_id));
+        }
+        self.premultiplied = true;
+        Ok(())
+    }
+
+    pub fn unpremultiply(&mut self) {
+        if !self.premultiplied {
+            return;
+        }
+        for state_id in &mut self.transitions {
+            *state_id = S::from_usize(state_id.to_usize() >> ALPHABET_SHIFT);
+        }
+        self.premultiplied = false;
+    }
+
+    /// Returns `true` if every state id of this DFA, stored the way this DFA stores them, fits in
+    /// `T`.
+    pub fn fits<T: StateIdRepr>(&self) -> bool {
+        let state_id = self.states.len() - 1;
+        if self.premultiplied {
+            premultiplied_state_id_fits::<T>(state_id)
+        } else {
+            state_id <= T::max_usize()
+        }
+    }
+
+    fn premultiplied_state_id(&self, state_id: StateId) -> usize {
+        let flag = if self.states[state_id].iter().any(Option::is_some) {
+            MATCH_FLAG
+        } else {
+            0
+        };
+        state_id << ALPHABET_SHIFT | flag
+    }
+
+    fn logical_state_id(&self, state_id: S) -> StateId {
+        if self.premultiplied {
+            state_id.to_usize() >> ALPHABET_SHIFT
+        } else {
+            state_id.to_usize()
+        }
+    }
+
+    /// Copies this DFA into a table with state ids of type `T`, keeping it premultiplied or not.
+    /// Returns `None` if the state ids do not fit in `T`.
+    pub fn to_repr<T: StateIdRepr>(&self) -> Option<Dfa<T>> {
+        if !self.fits::<T>() {
+            return None;
+        }
+        Some(Dfa {
+            states: self.states.clone(),
+            matched_token_sets: self.matched_token_sets.clone(),
+            marker_token_sets: self.marker_token_sets.clone(),
+            needs_lookahead: self.needs_lookahead.clone(),
+            start_state_ids: self.start_state_ids,
+            transitions: self
+                .transitions
+                .iter()
+                .map(|&state_id| T::from_usize(state_id.to_usize()))
+                .collect(),
+            token_count: self.token_count,
+            tokens: self.tokens.clone(),
+            premultiplied: self.premultiplied,
+        })
+    }
+
+    /// Picks the narrowest state id type that can hold this DFA's state ids as it stores them.
+    /// Row offsets never fit in a `u8`, so only an unpremultiplied DFA can end up as `U8`.
+    pub fn into_narrowest(self) -> AnyDfa {
+        if let Some(dfa) = self.to_repr::<u8>() {
+            AnyDfa::U8(dfa)
+        } else if let Some(dfa) = self.to_repr::<u16>() {
+            AnyDfa::U16(dfa)
+        } else if let Some(dfa) = self.to_repr::<u32>() {
+            AnyDfa::U32(dfa)
+        } else {
+            AnyDfa::Usize(self.to_repr::<usize>().unwrap())
+        }
+    }
+
+    /// Serializes this DFA with its token table. State ids are written with the width of `S`, so
+    /// the bytes can only be read back into a `Dfa<S>` with the same `S`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let width = mem::size_of::<S>();
+        let mut writer = Writer::new();
+        writer.u8(width as u8);
+        writer.bool(self.premultiplied);
+        writer.usize(self.token_count);
+        writer.usize(self.states.len());
+        writer.usizes(&self.start_state_ids);
+        for matched_tokens in self.matched_token_sets.iter().flatten() {
+            writer.usizes(matched_tokens);
+        }
+        for marker_tokens in &self.marker_token_sets {
+            writer.usizes(marker_tokens);
+        }
+        for &state_id in &self.transitions {
+            writer.uint(state_id.to_usize(), width);
+        }
+        writer.tokens(&self.tokens);
+        writer.into_bytes()
+    }
+
+    /// Reads a DFA written by `to_bytes`, checking that every token and state id is in range.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, serialize::Error> {
+        let width = mem::size_of::<S>();
+        let mut reader = Reader::new(bytes)?;
+        let stored_width = reader.u8()? as usize;
+        if stored_width != width {
+            return Err(serialize::Error::StateIdWidth(stored_width));
+        }
+        let premultiplied = reader.bool()?;
+        let token_count = reader.usize()?;
+        let state_count = reader.usize()?;
+        if state_count == 0 {
+            return Err(serialize::Error::InvalidValue(0));
+        }
+        let start_state_ids = reader.usizes()?;
+        let start_state_ids = <[StateId; BYTE_CLASS_COUNT]>::try_from(start_state_ids).map_err(
+            |start_state_ids| serialize::Error::InvalidValue(start_state_ids.len() as u64),
+        )?;
+        if let Some(&state_id) = start_state_ids
+            .iter()
+            .find(|&&state_id| state_id >= state_count)
+        {
+            return Err(serialize::Error::InvalidStateId(state_id));
+        }
+        let mut states = Vec::new();
+        let mut matched_token_sets = Vec::new();
+        let mut needs_lookahead = Vec::new();
+        for _ in 0..state_count {
+            let mut matched_token_by_class = [None; BYTE_CLASS_COUNT];
+            let mut matched_tokens_by_class: [Box<[usize]>; BYTE_CLASS_COUNT] = Default::default();
+            for class in 0..BYTE_CLASS_COUNT {
+                let matched_tokens = reader.usizes()?;
+                if let Some(&token) = matched_tokens.iter().find(|&&token| token >= token_count) {
+                    return Err(serialize::Error::InvalidToken(token));
+                }
+                matched_token_by_class[class] = matched_tokens.first().cloned();
+                matched_tokens_by_class[class] = matched_tokens.into();
+            }
+            needs_lookahead.push(
+                matched_token_by_class
+                    .iter()
+                    .any(|&matched_token| matched_token != matched_token_by_class[0]),
+            );
+            states.push(matched_token_by_class);
+            matched_token_sets.push(matched_tokens_by_class);
+        }
+        let mut marker_token_sets = Vec::new();
+        for _ in 0..state_count {
+            let marker_tokens = reader.usizes()?;
+            if let Some(&token) = marker_tokens.iter().find(|&&token| token >= token_count) {
+                return Err(serialize::Error::InvalidToken(token));
+            }
+            marker_token_sets.push(marker_tokens.into());
+        }
+        let mut dfa = Self {
+            states,
+            matched_token_sets,
+            marker_token_sets,
+            needs_lookahead,
+            start_state_ids,
+            transitions: Vec::new(),
+            token_count,
+            tokens: TokenTable::new(),
+            premultiplied,
+        };
+        let transition_count = state_count
+            .checked_mul(ALPHABET_LEN)
+            .ok_or(serialize::Error::InvalidValue(state_count as u64))?;
+        for _ in 0..transition_count {
+            let stored_state_id = reader.uint(width)?;
+            if stored_state_id > S::max_usize() {
+                return Err(serialize::Error::InvalidStateId(stored_state_id));
+            }
+            let state_id = dfa.logical_state_id(S::from_usize(stored_state_id));
+            if state_id >= state_count
+                || premultiplied && dfa.premultiplied_state_id(state_id) != stored_state_id
+            {
+                return Err(serialize::Error::InvalidStateId(stored_state_id));
+            }
+            dfa.transitions.push(S::from_usize(stored_state_id));
+        }
+        dfa.tokens = reader.tokens()?;
+        reader.finish()?;
+        Ok(dfa)
+    }
+}
+
+impl Dfa {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
     pub fn add_state(&mut self, matched_token: Option<usize>) -> StateId {
+        self.add_state_with_matched_tokens(matched_token.as_slice())
+    }
+
+    /// Adds a state matching `matched_tokens`, which are ordered by priority: the first one is
+    /// reported as the matched token.
+    pub fn add_state_with_matched_tokens(&mut self, matched_tokens: &[usize]) -> StateId {
         let state_id = self.states.len();
-        self.states.push(matched_token);
+        self.states.push([None; BYTE_CLASS_COUNT]);
+        self.matched_token_sets.push(Default::default());
+        self.marker_token_sets.push(Box::new([]));
+        self.needs_lookahead.push(false);
         self.transitions
-            .extend(iter::repeat(dead_state_id()).take((u8::MAX as usize) + 1));
-        if let Some(matched_token) = matched_token {
-            self.token_count = self.token_count.max(matched_token + 1);
+            .resize(self.transitions.len() + ALPHABET_LEN, dead_state_id());
+        for class in 0..BYTE_CLASS_COUNT {
+            self.set_matched_tokens_at(state_id, class, matched_tokens);
         }
         state_id
     }
 
+    /// Sets the tokens `state_id` matches when the next byte, or the end of input, is of class
+    /// `next`.
+    pub fn set_matched_tokens_before(
+        &mut self,
+        state_id: StateId,
+        next: ByteClass,
+        matched_tokens: &[usize],
+    ) {
+        let was_matched = self.states[state_id].iter().any(Option::is_some);
+        self.set_matched_tokens_at(state_id, next as usize, matched_tokens);
+        if self.premultiplied && self.states[state_id].iter().any(Option::is_some) != was_matched {
+            self.refresh_match_flags(state_id);
+        }
+    }
+
+    fn set_matched_tokens_at(&mut self, state_id: StateId, class: usize, matched_tokens: &[usize]) {
+        self.states[state_id][class] = matched_tokens.first().cloned();
+        self.matched_token_sets[state_id][class] = matched_tokens.into();
+        let matched_token_by_class = &self.states[state_id];
+        self.needs_lookahead[state_id] = matched_token_by_class
+            .iter()
+            .any(|&matched_token| matched_token != matched_token_by_class[0]);
+        if let Some(&matched_token) = matched_tokens.iter().max() {
+            self.token_count = self.token_count.max(matched_token + 1);
+        }
+    }
+
+    // Transitions into `state_id` that were added before it started or stopped matching carry a
+    // stale match flag.
+    fn refresh_match_flags(&mut self, state_id: StateId) {
+        let premultiplied_state_id = self.premultiplied_state_id(state_id);
+        for stored_state_id in &mut self.transitions {
+            if *stored_state_id >> ALPHABET_SHIFT == state_id {
+                *stored_state_id = premultiplied_state_id;
+            }
+        }
+    }
+
+    pub fn set_start_state_id(&mut self, previous: ByteClass, state_id: StateId) {
+        self.start_state_ids[previous as usize] = state_id;
+    }
+
+    pub fn set_marker_tokens(&mut self, state_id: StateId, marker_tokens: &[usize]) {
+        self.marker_token_sets[state_id] = marker_tokens.into();
+    }
+
     pub fn add_transition(&mut self, state_id: StateId, byte: u8, next_state_id: StateId) {
-        let offset = state_id * (u8::MAX as usize + 1) + byte as usize;
-        self.transitions[offset] = next_state_id;
+        let offset = state_id * ALPHABET_LEN + byte as usize;
+        self.transitions[offset] = if self.premultiplied {
+            self.premultiplied_state_id(next_state_id)
+        } else {
+            next_state_id
+        };
     }
 }
 
@@ -74,64 +669,186 @@ impl Default for Dfa {
     fn default() -> Self {
         let mut dfa = Self {
             states: Vec::new(),
+            matched_token_sets: Vec::new(),
+            marker_token_sets: Vec::new(),
+            needs_lookahead: Vec::new(),
+            start_state_ids: [start_state_id(); BYTE_CLASS_COUNT],
             transitions: Vec::new(),
             token_count: 0,
+            tokens: TokenTable::new(),
+            premultiplied: true,
         };
         dfa.add_state(None);
         dfa
     }
 }
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct StateIdOverflow;
+
+impl Display for StateIdOverflow {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "state ids do not fit in the state id type")
+    }
+}
+
+impl Error for StateIdOverflow {}
+
+#[derive(Clone, Debug)]
+pub enum AnyDfa {
+    U8(Dfa<u8>),
+    U16(Dfa<u16>),
+    U32(Dfa<u32>),
+    Usize(Dfa<usize>),
+}
+
+impl AnyDfa {
+    pub fn state_count(&self) -> usize {
+        match self {
+            Self::U8(dfa) => dfa.state_count(),
+            Self::U16(dfa) => dfa.state_count(),
+            Self::U32(dfa) => dfa.state_count(),
+            Self::Usize(dfa) => dfa.state_count(),
+        }
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        match self {
+            Self::U8(dfa) => dfa.memory_usage(),
+            Self::U16(dfa) => dfa.memory_usage(),
+            Self::U32(dfa) => dfa.memory_usage(),
+            Self::Usize(dfa) => dfa.memory_usage(),
+        }
+    }
+
+    pub fn longest_match<B>(&self, bytes: B) -> Option<(usize, B)>
+    where
+        B: Clone + Iterator<Item = u8>,
+    {
+        match self {
+            Self::U8(dfa) => dfa.longest_match(bytes),
+            Self::U16(dfa) => dfa.longest_match(bytes),
+            Self::U32(dfa) => dfa.longest_match(bytes),
+            Self::Usize(dfa) => dfa.longest_match(bytes),
+        }
+    }
+}
+
 pub type StateId = usize;
 
+pub trait StateIdRepr: Copy + Debug + Default + Eq + Hash + Ord {
+    fn max_usize() -> usize;
+
+    fn from_usize(state_id: usize) -> Self;
+
+    fn to_usize(self) -> usize;
+}
+
+macro_rules! impl_state_id_repr {
+    ($($T:ty),*) => {
+        $(
+            impl StateIdRepr for $T {
+                fn max_usize() -> usize {
+                    <$T>::MAX as usize
+                }
+
+                fn from_usize(state_id: usize) -> Self {
+                    assert!(state_id <= Self::max_usize());
+                    state_id as $T
+                }
+
+                fn to_usize(self) -> usize {
+                    self as usize
+                }
+            }
+        )*
+    };
+}
+
+impl_state_id_repr!(u8, u16, u32, usize);
+
 #[derive(Debug)]
-pub struct States<'a> {
-    iter: Enumerate<Zip<Cloned<Iter<'a, Option<usize>>>, Chunks<'a, StateId>>>,
+pub struct States<'a, S> {
+    dfa: &'a Dfa<S>,
+    state_id: StateId,
 }
 
-impl<'a> Iterator for States<'a> {
-    type Item = (StateId, State<'a>);
+impl<'a, S: StateIdRepr> Iterator for States<'a, S> {
+    type Item = (StateId, State<'a, S>);
 
     fn next(&mut self) -> Option<Self::Item> {
-        let (state_id, (matched_token, transitions)) = self.iter.next()?;
-        Some((
-            state_id,
-            State {
-                matched_token,
-                transitions,
-            },
-        ))
+        if self.state_id == self.dfa.state_count() {
+            return None;
+        }
+        let state_id = self.state_id;
+        self.state_id += 1;
+        Some((state_id, self.dfa.state(state_id)))
     }
 }
 
 #[derive(Debug)]
-pub struct State<'a> {
-    matched_token: Option<usize>,
-    transitions: &'a [StateId],
+pub struct State<'a, S> {
+    matched_token_by_class: &'a [Option<usize>; BYTE_CLASS_COUNT],
+    matched_tokens_by_class: &'a [Box<[usize]>; BYTE_CLASS_COUNT],
+    marker_tokens: &'a [usize],
+    transitions: &'a [S],
+    premultiplied: bool,
 }
 
-impl<'a> State<'a> {
+impl<'a, S: StateIdRepr> State<'a, S> {
+    /// Returns the token matched at the end of input.
     pub fn matched_token(&self) -> Option<usize> {
-        self.matched_token
+        self.matched_token_by_class[ByteClass::Boundary as usize]
     }
 
-    pub fn transitions(&self) -> Transitions<'a> {
+    pub fn matched_tokens(&self) -> &'a [usize] {
+        &self.matched_tokens_by_class[ByteClass::Boundary as usize]
+    }
+
+    /// Returns the token matched when `next` follows, or `None` at the end of input.
+    pub fn matched_token_before(&self, next: Option<u8>) -> Option<usize> {
+        self.matched_token_by_class[ByteClass::of(next) as usize]
+    }
+
+    pub fn matched_tokens_before(&self, next: Option<u8>) -> &'a [usize] {
+        &self.matched_tokens_by_class[ByteClass::of(next) as usize]
+    }
+
+    pub fn matched_token_by_class(&self) -> &'a [Option<usize>; BYTE_CLASS_COUNT] {
+        self.matched_token_by_class
+    }
+
+    pub fn matched_tokens_by_class(&self) -> &'a [Box<[usize]>; BYTE_CLASS_COUNT] {
+        self.matched_tokens_by_class
+    }
+
+    pub fn marker_tokens(&self) -> &'a [usize] {
+        self.marker_tokens
+    }
+
+    pub fn transitions(&self) -> Transitions<'a, S> {
         Transitions {
             iter: self.transitions.iter().cloned().enumerate(),
+            premultiplied: self.premultiplied,
         }
     }
 }
 
 #[derive(Debug)]
-pub struct Transitions<'a> {
-    iter: Enumerate<Cloned<Iter<'a, StateId>>>,
+pub struct Transitions<'a, S> {
+    iter: Enumerate<Cloned<Iter<'a, S>>>,
+    premultiplied: bool,
 }
 
-impl<'a> Iterator for Transitions<'a> {
+impl<'a, S: StateIdRepr> Iterator for Transitions<'a, S> {
     type Item = Transition;
 
     fn next(&mut self) -> Option<Self::Item> {
         let (byte, next_state_id) = self.iter.next()?;
+        let mut next_state_id = next_state_id.to_usize();
+        if self.premultiplied {
+            next_state_id >>= ALPHABET_SHIFT;
+        }
         Some(Transition {
             byte: byte as u8,
             next_state_id,
@@ -145,6 +862,20 @@ pub struct Transition {
     pub next_state_id: StateId,
 }
 
+pub const ALPHABET_LEN: usize = u8::MAX as usize + 1;
+
+const ALPHABET_SHIFT: u32 = ALPHABET_LEN.trailing_zeros();
+
+/// Set in the low bits of a premultiplied state id if the state matches a token. Row offsets are
+/// multiples of `ALPHABET_LEN`, so the flag never collides with them.
+const MATCH_FLAG: usize = 1;
+
+fn premultiplied_state_id_fits<T: StateIdRepr>(state_id: StateId) -> bool {
+    state_id
+        .checked_mul(ALPHABET_LEN)
+        .is_some_and(|offset| offset | MATCH_FLAG <= T::max_usize())
+}
+
 pub fn dead_state_id() -> StateId {
     0
 }
@@ -152,3 +883,239 @@ pub fn dead_state_id() -> StateId {
 pub fn start_state_id() -> StateId {
     1
 }
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{determinizer::Determinizer, parser::Parser},
+    };
+
+    fn dfa(pattern: &str) -> Dfa {
+        let mut parser = Parser::new();
+        parser.parse(pattern, 0).unwrap();
+        Determinizer::new(&parser.build()).determinize()
+    }
+
+    fn longest_match_len<S: StateIdRepr>(dfa: &Dfa<S>, bytes: &[u8]) -> Option<usize> {
+        dfa.longest_match(bytes.iter().cloned())
+            .map(|(_, rest)| bytes.len() - rest.len())
+    }
+
+    #[test]
+    fn premultiplied_and_unpremultiplied_longest_match_agree() {
+        let premultiplied = dfa("a(bc)*|bd");
+        assert!(premultiplied.is_premultiplied());
+        let mut unpremultiplied = premultiplied.clone();
+        unpremultiplied.unpremultiply();
+        for bytes in [&b"abcbcx"[..], b"bd", b"b", b"abcb"] {
+            assert_eq!(
+                longest_match_len(&premultiplied, bytes),
+                longest_match_len(&unpremultiplied, bytes)
+            );
+        }
+        assert_eq!(longest_match_len(&premultiplied, b"abcbcx"), Some(5));
+    }
+
+    #[test]
+    fn premultiply_fails_when_offsets_do_not_fit() {
+        let mut dfa = Dfa::new();
+        for _ in 0..300 {
+            dfa.add_state(None);
+        }
+        dfa.unpremultiply();
+        let mut dfa = dfa.to_repr::<u16>().unwrap();
+        assert_eq!(dfa.premultiply(), Err(StateIdOverflow));
+        assert!(!dfa.is_premultiplied());
+    }
+
+    #[test]
+    fn into_narrowest_keeps_premultiplication() {
+        let dfa = dfa("abc");
+        assert!(matches!(dfa.clone().into_narrowest(), AnyDfa::U16(dfa) if dfa.is_premultiplied()));
+        let mut unpremultiplied = dfa;
+        unpremultiplied.unpremultiply();
+        assert!(matches!(unpremultiplied.into_narrowest(), AnyDfa::U8(_)));
+    }
+
+    fn matched_token(dfa: &Dfa, bytes: &[u8]) -> Option<usize> {
+        let state_id = bytes.iter().fold(start_state_id(), |state_id, &byte| {
+            dfa.next_state_id(state_id, byte)
+        });
+        dfa.state(state_id).matched_token()
+    }
+
+    fn longest_match_len_after(dfa: &Dfa, previous: Option<u8>, bytes: &[u8]) -> Option<usize> {
+        let (_, rest) = dfa.longest_match_after(previous, bytes.iter().cloned())?;
+        Some(bytes.len() - rest.len())
+    }
+
+    #[test]
+    fn longest_match_checks_assertions_against_the_next_byte() {
+        let mut dfa = dfa("[a-z]+\\b|[a-z]+$");
+        assert!(dfa.is_context_dependent());
+        for _ in 0..2 {
+            assert_eq!(longest_match_len_after(&dfa, None, b"abc"), Some(3));
+            assert_eq!(longest_match_len_after(&dfa, None, b"abc d"), Some(3));
+            assert_eq!(longest_match_len_after(&dfa, None, b"abc\nd"), Some(3));
+            assert_eq!(longest_match_len_after(&dfa, None, b"abc_"), None);
+            dfa.unpremultiply();
+        }
+        let dfa = self::dfa("a\\Bb*");
+        assert_eq!(longest_match_len_after(&dfa, None, b"abb"), Some(3));
+        assert_eq!(longest_match_len_after(&dfa, None, b"a b"), None);
+    }
+
+    #[test]
+    fn start_states_depend_on_the_previous_byte() {
+        let dfa = dfa("^a|\\ba");
+        assert_eq!(longest_match_len_after(&dfa, None, b"a"), Some(1));
+        assert_eq!(longest_match_len_after(&dfa, Some(b'\n'), b"a"), Some(1));
+        assert_eq!(longest_match_len_after(&dfa, Some(b' '), b"a"), Some(1));
+        assert_eq!(longest_match_len_after(&dfa, Some(b'x'), b"a"), None);
+        let dfa = self::dfa("\\Aa\\z");
+        assert_eq!(longest_match_len_after(&dfa, None, b"a"), Some(1));
+        assert_eq!(longest_match_len_after(&dfa, Some(b'\n'), b"a"), None);
+        assert_eq!(longest_match_len_after(&dfa, None, b"a\n"), None);
+    }
+
+    #[test]
+    fn match_flags_follow_late_matched_tokens() {
+        let mut dfa = Dfa::new();
+        let state_id = dfa.add_state(None);
+        dfa.add_transition(start_state_id(), b'a', state_id);
+        dfa.set_matched_tokens_before(state_id, ByteClass::Boundary, &[0]);
+        assert_eq!(longest_match_len(&dfa, b"a"), Some(1));
+        dfa.set_matched_tokens_before(state_id, ByteClass::Boundary, &[]);
+        assert_eq!(longest_match_len(&dfa, b"a"), None);
+    }
+
+    #[test]
+    fn complement_keeps_the_context() {
+        let dfa = dfa("\\ba$");
+        let complement = dfa.complement();
+        let state_id = complement.next_state_id(complement.start_state_id_after(None), b'a');
+        let state = complement.state(state_id);
+        assert_eq!(state.matched_tokens_before(None), &[] as &[usize]);
+        assert_eq!(state.matched_tokens_before(Some(b'\n')), &[] as &[usize]);
+        assert_eq!(state.matched_tokens_before(Some(b'x')), &[1]);
+        let state_id = complement.next_state_id(complement.start_state_id_after(Some(b'x')), b'a');
+        assert_eq!(
+            complement.state(state_id).matched_token_before(None),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn complement_keeps_the_token_table() {
+        let mut dfa = dfa("a");
+        dfa.tokens_mut().insert(
+            0,
+            Token {
+                name: "A".to_string(),
+                pattern: "a".to_string(),
+                priority: 0,
+                skip: false,
+            },
+        );
+        let complement = dfa.complement();
+        assert_eq!(complement.tokens().get(0), dfa.tokens().get(0));
+        assert_eq!(complement.tokens().name(1), Some("COMPLEMENT_1"));
+        assert_eq!(matched_token(&complement, b"b"), Some(1));
+    }
+
+    #[test]
+    fn complement_accepts_exactly_the_rejected_strings() {
+        let complement = dfa("(a|b|c)+").complement();
+        assert_eq!(complement.token_count(), 2);
+        assert_eq!(matched_token(&complement, b""), Some(1));
+        assert_eq!(matched_token(&complement, b"abc"), None);
+        assert_eq!(matched_token(&complement, b"ab1"), Some(1));
+        assert_eq!(matched_token(&complement, b"ab1cd"), Some(1));
+        let complement = complement.complement();
+        assert_eq!(matched_token(&complement, b""), None);
+        assert_eq!(matched_token(&complement, b"abc"), Some(2));
+        assert_eq!(matched_token(&complement, b"ab1"), None);
+    }
+
+    #[test]
+    fn serialization_round_trips() {
+        let mut parser = Parser::new();
+        parser.parse("(a|b)+", 0).unwrap();
+        parser.parse("a", 1).unwrap();
+        for (token, name, pattern, skip) in [(0, "AB", "(a|b)+", false), (1, "A", "a", true)] {
+            parser.tokens_mut().insert(
+                token,
+                Token {
+                    name: name.to_string(),
+                    pattern: pattern.to_string(),
+                    priority: 1 - token,
+                    skip,
+                },
+            );
+        }
+        let dfa = Determinizer::new(&parser.build()).determinize();
+        let bytes = dfa.to_bytes();
+        let deserialized = Dfa::<usize>::from_bytes(&bytes).unwrap();
+        assert!(deserialized.is_premultiplied());
+        assert_eq!(deserialized.tokens(), dfa.tokens());
+        assert_eq!(deserialized.to_bytes(), bytes);
+        let state_id = deserialized.next_state_id(start_state_id(), b'a');
+        assert_eq!(deserialized.state(state_id).matched_tokens(), &[1, 0]);
+        assert_eq!(longest_match_len(&deserialized, b"abx"), Some(2));
+
+        let mut unpremultiplied = dfa;
+        unpremultiplied.unpremultiply();
+        let unpremultiplied = unpremultiplied.to_repr::<u8>().unwrap();
+        let deserialized = Dfa::<u8>::from_bytes(&unpremultiplied.to_bytes()).unwrap();
+        assert!(!deserialized.is_premultiplied());
+        assert_eq!(longest_match_len(&deserialized, b"abx"), Some(2));
+    }
+
+    #[test]
+    fn serialization_keeps_the_context() {
+        let dfa = dfa("^a|a\\b");
+        let deserialized = Dfa::<usize>::from_bytes(&dfa.to_bytes()).unwrap();
+        assert!(deserialized.is_context_dependent());
+        for (previous, bytes) in [(None, &b"a"[..]), (Some(b'x'), b"a "), (Some(b'x'), b"ab")] {
+            assert_eq!(
+                longest_match_len_after(&deserialized, previous, bytes),
+                longest_match_len_after(&dfa, previous, bytes)
+            );
+        }
+    }
+
+    #[test]
+    fn marker_tokens_survive_complement_and_serialization() {
+        let dfa = dfa("ab/cd");
+        let state_id = dfa.next_state_id(dfa.next_state_id(start_state_id(), b'a'), b'b');
+        assert_eq!(dfa.state(state_id).marker_tokens(), &[0]);
+        let complement = dfa.complement();
+        assert_eq!(complement.state(state_id).marker_tokens(), &[0]);
+        let deserialized = Dfa::<usize>::from_bytes(&dfa.to_bytes()).unwrap();
+        assert_eq!(deserialized.state(state_id).marker_tokens(), &[0]);
+    }
+
+    #[test]
+    fn deserialization_rejects_malformed_input() {
+        let bytes = dfa("ab").to_bytes();
+        assert_eq!(
+            Dfa::<u32>::from_bytes(&bytes).unwrap_err(),
+            serialize::Error::StateIdWidth(mem::size_of::<usize>())
+        );
+        assert_eq!(
+            Dfa::<usize>::from_bytes(&bytes[..bytes.len() - 1]).unwrap_err(),
+            serialize::Error::UnexpectedEnd
+        );
+        assert_eq!(
+            Dfa::<usize>::from_bytes(b"not a dfa").unwrap_err(),
+            serialize::Error::InvalidHeader
+        );
+        let mut bytes = bytes;
+        bytes.push(0);
+        assert_eq!(
+            Dfa::<usize>::from_bytes(&bytes).unwrap_err(),
+            serialize::Error::TrailingBytes
+        );
+    }
+}
@@ -0,0 +1,40 @@
+use {
+    crate::{determinizer::StateLimitExceeded, parser},
+    std::{error, fmt},
+};
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Error {
+    Parse(parser::Error),
+    StateLimitExceeded,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Parse(error) => write!(f, "parse error: {}", error),
+            Self::StateLimitExceeded => StateLimitExceeded.fmt(f),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Parse(error) => Some(error),
+            Self::StateLimitExceeded => None,
+        }
+    }
+}
+
+impl From<parser::Error> for Error {
+    fn from(error: parser::Error) -> Self {
+        Self::Parse(error)
+    }
+}
+
+impl From<StateLimitExceeded> for Error {
+    fn from(_: StateLimitExceeded) -> Self {
+        Self::StateLimitExceeded
+    }
+}
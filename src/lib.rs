@@ -1,7 +1,29 @@
+pub use {
+    error::Error,
+    regex::{Lexer, LexerBuilder, Regex},
+};
+
+pub mod analysis;
+pub mod ast;
+mod compiler;
+pub mod compressed_dfa;
 pub mod determinizer;
 pub mod dfa;
+pub mod error;
 pub mod minimizer;
 pub mod nfa;
 pub mod parser;
+pub mod product;
+pub mod regex;
+pub mod sampler;
+pub mod searcher;
+pub mod serialize;
+pub mod simplifier;
 pub mod sparse_set;
+pub mod spec;
+pub mod state_eliminator;
+pub mod strings;
+pub mod token;
+pub mod unicode;
+pub mod utf8;
 pub mod vec_set;
@@ -1,98 +1,177 @@
 use {
     crate::{
-        dfa::{Dfa, StateId},
+        dfa::{self, Dfa, StateId, StateIdRepr, ALPHABET_LEN},
+        nfa::BYTE_CLASSES,
         vec_set::VecSet,
     },
-    std::mem,
+    std::{collections::HashMap, mem},
 };
 
-pub struct Minimizer {
+pub struct Minimizer<'a, S> {
+    dfa: &'a Dfa<S>,
     incoming_transitions: Vec<Vec<StateId>>,
     partitions: Vec<VecSet<StateId>>,
+    partition_ids: Vec<PartitionId>,
     partition_id_stack: Vec<PartitionId>,
 }
 
-impl Minimizer {
-    pub fn new(dfa: &Dfa) -> Self {
+impl<'a, S: StateIdRepr> Minimizer<'a, S> {
+    pub fn new(dfa: &'a Dfa<S>) -> Self {
+        let partitions = initial_partitions(dfa);
+        let mut partition_ids = vec![0; dfa.state_count()];
+        for (partition_id, partition) in partitions.iter().enumerate() {
+            for &state_id in partition {
+                partition_ids[state_id] = partition_id;
+            }
+        }
         Self {
+            dfa,
             incoming_transitions: incoming_transitions(dfa),
-            partitions: initial_partitions(dfa),
-            partition_id_stack: vec![0],
+            partition_id_stack: (0..partitions.len()).collect(),
+            partitions,
+            partition_ids,
         }
     }
 
-    pub fn minimize(
-        &mut self,
-        previous_state_id_set: &mut VecSet<StateId>,
-        state_id_set_0: &mut VecSet<StateId>,
-        state_id_set_1: &mut VecSet<StateId>,
-    ) {
+    pub fn minimize(mut self) -> Dfa {
+        let mut previous_state_id_set = VecSet::new();
+        let mut state_id_set_0 = VecSet::new();
+        let mut state_id_set_1 = VecSet::new();
+        let mut candidate_partition_ids = Vec::new();
         while let Some(partition_id) = self.partition_id_stack.pop() {
-            for byte in 0..u8::MAX {
-                self.previous_states(partition_id, byte, previous_state_id_set);
-                // TODO: Only iterate over those partitions that contain a previous state
-                for partition_id in 0..self.partitions.len() {
+            let splitter = self.partitions[partition_id].clone();
+            for byte in 0..=u8::MAX {
+                self.previous_states(&splitter, byte, &mut previous_state_id_set);
+                candidate_partition_ids.clear();
+                candidate_partition_ids.extend(
+                    previous_state_id_set
+                        .iter()
+                        .map(|&state_id| self.partition_ids[state_id]),
+                );
+                candidate_partition_ids.sort_unstable();
+                candidate_partition_ids.dedup();
+                for &partition_id in &candidate_partition_ids {
                     self.partitions[partition_id]
                         .intersection(&previous_state_id_set)
-                        .into_vec_set(state_id_set_0);
+                        .into_vec_set(&mut state_id_set_0);
                     if state_id_set_0.is_empty() {
                         continue;
                     }
                     self.partitions[partition_id]
                         .difference(&previous_state_id_set)
-                        .into_vec_set(state_id_set_1);
+                        .into_vec_set(&mut state_id_set_1);
                     if state_id_set_1.is_empty() {
                         continue;
                     }
-                    mem::swap(&mut self.partitions[partition_id], state_id_set_0);
-                    self.partitions.push(state_id_set_1.clone());
+                    let new_partition_id = self.partitions.len();
+                    for &state_id in &state_id_set_1 {
+                        self.partition_ids[state_id] = new_partition_id;
+                    }
+                    mem::swap(&mut self.partitions[partition_id], &mut state_id_set_0);
+                    self.partitions.push(mem::take(&mut state_id_set_1));
+                    self.partition_id_stack.push(partition_id);
+                    self.partition_id_stack.push(new_partition_id);
                 }
             }
         }
+        self.build()
     }
 
-    pub fn previous_states(
-        &mut self,
-        partition_id: PartitionId,
+    fn previous_states(
+        &self,
+        partition: &VecSet<StateId>,
         byte: u8,
         previous_state_id_set: &mut VecSet<StateId>,
     ) {
-        let mut previous_state_ids = mem::replace(previous_state_id_set, VecSet::new()).into_vec();
+        let mut previous_state_ids = mem::take(previous_state_id_set).into_vec();
         previous_state_ids.clear();
-        for &state_id in &self.partitions[partition_id] {
-            let offset = state_id * (u8::MAX as usize + 1) + byte as usize;
+        for &state_id in partition {
+            let offset = state_id * ALPHABET_LEN + byte as usize;
             previous_state_ids.extend(&self.incoming_transitions[offset]);
         }
-        previous_state_ids.sort();
+        previous_state_ids.sort_unstable();
         previous_state_ids.dedup();
         *previous_state_id_set = unsafe { VecSet::from_vec_unchecked(previous_state_ids) };
     }
+
+    fn build(self) -> Dfa {
+        let dead_partition_id = self.partition_ids[dfa::dead_state_id()];
+        let start_partition_id = self.partition_ids[dfa::start_state_id()];
+        let mut new_state_ids = vec![None; self.partitions.len()];
+        new_state_ids[dead_partition_id] = Some(dfa::dead_state_id());
+        let mut representatives = vec![dfa::dead_state_id()];
+        if start_partition_id != dead_partition_id {
+            new_state_ids[start_partition_id] = Some(dfa::start_state_id());
+        }
+        representatives.push(dfa::start_state_id());
+        for (partition_id, partition) in self.partitions.iter().enumerate() {
+            if new_state_ids[partition_id].is_none() {
+                new_state_ids[partition_id] = Some(representatives.len());
+                representatives.push(*partition.iter().next().unwrap());
+            }
+        }
+        let mut dfa = Dfa::new();
+        *dfa.tokens_mut() = self.dfa.tokens().clone();
+        for &state_id in &representatives[1..] {
+            dfa.add_state_with_matched_tokens(self.dfa.state(state_id).matched_tokens());
+        }
+        for (new_state_id, &state_id) in representatives.iter().enumerate() {
+            let state = self.dfa.state(state_id);
+            for (class, matched_tokens) in BYTE_CLASSES.iter().zip(state.matched_tokens_by_class())
+            {
+                dfa.set_matched_tokens_before(new_state_id, *class, matched_tokens);
+            }
+            dfa.set_marker_tokens(new_state_id, state.marker_tokens());
+        }
+        for &class in &BYTE_CLASSES {
+            let start_state_id = self.dfa.start_state_id_for(class);
+            let partition_id = self.partition_ids[start_state_id];
+            dfa.set_start_state_id(class, new_state_ids[partition_id].unwrap());
+        }
+        for (new_state_id, &state_id) in representatives.iter().enumerate() {
+            if new_state_id == dfa::start_state_id() && start_partition_id == dead_partition_id {
+                continue;
+            }
+            for transition in self.dfa.state(state_id).transitions() {
+                let next_partition_id = self.partition_ids[transition.next_state_id];
+                dfa.add_transition(
+                    new_state_id,
+                    transition.byte,
+                    new_state_ids[next_partition_id].unwrap(),
+                );
+            }
+        }
+        dfa
+    }
 }
 
 type PartitionId = usize;
 
-fn incoming_transitions(dfa: &Dfa) -> Vec<Vec<StateId>> {
-    let mut incoming_transitions = vec![Vec::new(); dfa.state_count() * u8::MAX as usize];
+fn incoming_transitions<S: StateIdRepr>(dfa: &Dfa<S>) -> Vec<Vec<StateId>> {
+    let mut incoming_transitions = vec![Vec::new(); dfa.state_count() * ALPHABET_LEN];
     for (state_id, state) in dfa.states() {
         for transition in state.transitions() {
-            let offset = state_id * (u8::MAX as usize + 1) + transition.byte as usize;
+            let offset = transition.next_state_id * ALPHABET_LEN + transition.byte as usize;
             incoming_transitions[offset].push(state_id);
         }
     }
     incoming_transitions
 }
 
-fn initial_partitions(dfa: &Dfa) -> Vec<VecSet<StateId>> {
-    let mut partitions = vec![Vec::new(); dfa.token_count() + 1];
+fn initial_partitions<S: StateIdRepr>(dfa: &Dfa<S>) -> Vec<VecSet<StateId>> {
+    let mut partitions = Vec::new();
+    let mut partition_ids_by_tokens = HashMap::new();
     for (state_id, state) in dfa.states() {
-        match state.matched_token() {
-            Some(matched_token) => &mut partitions[matched_token + 1],
-            None => &mut partitions[0],
-        }
-        .push(state_id)
+        let partition_id = *partition_ids_by_tokens
+            .entry((state.matched_tokens_by_class(), state.marker_tokens()))
+            .or_insert_with(|| {
+                partitions.push(Vec::new());
+                partitions.len() - 1
+            });
+        partitions[partition_id].push(state_id);
     }
     partitions
         .into_iter()
-        .map(|partitions| unsafe { VecSet::from_vec_unchecked(partitions) })
+        .map(|partition| unsafe { VecSet::from_vec_unchecked(partition) })
         .collect::<Vec<_>>()
 }
@@ -1,9 +1,14 @@
-use crate::sparse_set::SparseSet;
+use {
+    crate::{sparse_set::SparseSet, token::TokenTable, utf8::Utf8Sequences},
+    std::collections::HashMap,
+};
 
 #[derive(Debug, Default)]
 pub struct Nfa {
     states: Vec<State>,
     fragments: Vec<Fragment>,
+    trailing_context_fragments: Vec<Fragment>,
+    tokens: TokenTable,
 }
 
 impl Nfa {
@@ -23,11 +28,49 @@ impl Nfa {
         &self.fragments
     }
 
+    pub fn trailing_context_fragments(&self) -> &[Fragment] {
+        &self.trailing_context_fragments
+    }
+
+    pub fn tokens(&self) -> &TokenTable {
+        &self.tokens
+    }
+
+    pub fn tokens_mut(&mut self) -> &mut TokenTable {
+        &mut self.tokens
+    }
+
+    pub fn has_assertions(&self) -> bool {
+        self.states.iter().any(|state| {
+            state
+                .transitions
+                .iter()
+                .any(|transition| transition.assertion.is_some())
+        })
+    }
+
     pub fn empty_closure(
         &self,
         state_id: StateId,
         state_id_set: &mut SparseSet,
         state_id_stack: &mut Vec<StateId>,
+    ) {
+        self.closure(
+            state_id,
+            ByteClass::Boundary,
+            None,
+            state_id_set,
+            state_id_stack,
+        );
+    }
+
+    pub fn closure(
+        &self,
+        state_id: StateId,
+        previous: ByteClass,
+        next: Option<ByteClass>,
+        state_id_set: &mut SparseSet,
+        state_id_stack: &mut Vec<StateId>,
     ) {
         state_id_stack.clear();
         state_id_stack.push(state_id);
@@ -40,16 +83,98 @@ impl Nfa {
                 self.states[state_id]
                     .transitions
                     .iter()
-                    .filter(|transition| transition.is_empty())
+                    .rev()
+                    .filter(|transition| {
+                        transition.is_empty()
+                            || transition
+                                .assertion
+                                .is_some_and(|assertion| assertion.is_satisfied(previous, next))
+                    })
                     .map(|transition| transition.next_state_id),
             );
         }
     }
 
+    pub fn reverse(&self) -> Nfa {
+        self.reverse_fragments(&self.fragments)
+    }
+
+    pub fn reverse_trailing_contexts(&self) -> Nfa {
+        self.reverse_fragments(&self.trailing_context_fragments)
+    }
+
+    fn reverse_fragments(&self, fragments: &[Fragment]) -> Nfa {
+        let mut incoming_state_ids = vec![Vec::new(); self.states.len()];
+        for (state_id, state) in self.states.iter().enumerate() {
+            for transition in &state.transitions {
+                incoming_state_ids[transition.next_state_id].push(state_id);
+            }
+        }
+        let mut co_reachable_state_id_set = SparseSet::new(self.states.len());
+        let mut useful_state_id_set = SparseSet::new(self.states.len());
+        let mut state_id_stack = Vec::new();
+        let mut new_state_ids = vec![0; self.states.len()];
+        let mut nfa = Nfa::new();
+        nfa.tokens = self.tokens.clone();
+        for &fragment in fragments {
+            co_reachable_state_id_set.clear();
+            state_id_stack.push(fragment.end_state_id);
+            while let Some(state_id) = state_id_stack.pop() {
+                if co_reachable_state_id_set.insert(state_id) {
+                    state_id_stack.extend(&incoming_state_ids[state_id]);
+                }
+            }
+            useful_state_id_set.clear();
+            state_id_stack.push(fragment.start_state_id);
+            while let Some(state_id) = state_id_stack.pop() {
+                if co_reachable_state_id_set.contains(state_id)
+                    && useful_state_id_set.insert(state_id)
+                {
+                    state_id_stack.extend(
+                        self.states[state_id]
+                            .transitions
+                            .iter()
+                            .map(|transition| transition.next_state_id),
+                    );
+                }
+            }
+            for state_id in &useful_state_id_set {
+                new_state_ids[state_id] = nfa.add_state();
+            }
+            for state_id in &useful_state_id_set {
+                for transition in &self.states[state_id].transitions {
+                    if !useful_state_id_set.contains(transition.next_state_id) {
+                        continue;
+                    }
+                    match transition.assertion {
+                        Some(assertion) => nfa.add_assertion_transition(
+                            new_state_ids[transition.next_state_id],
+                            assertion.reverse(),
+                            new_state_ids[state_id],
+                        ),
+                        None => nfa.add_transition(
+                            new_state_ids[transition.next_state_id],
+                            transition.byte_range,
+                            new_state_ids[state_id],
+                        ),
+                    }
+                }
+            }
+            nfa.states[new_state_ids[fragment.start_state_id]].matched_token =
+                self.states[fragment.end_state_id].matched_token;
+            nfa.fragments.push(Fragment {
+                start_state_id: new_state_ids[fragment.end_state_id],
+                end_state_id: new_state_ids[fragment.start_state_id],
+            });
+        }
+        nfa
+    }
+
     pub fn add_state(&mut self) -> StateId {
         let state_id = self.states.len();
         self.states.push(State {
             matched_token: None,
+            marker_token: None,
             transitions: vec![],
         });
         state_id
@@ -64,6 +189,21 @@ impl Nfa {
         let state = &mut self.states[state_id];
         state.transitions.push(Transition {
             byte_range,
+            assertion: None,
+            next_state_id,
+        });
+    }
+
+    pub fn add_assertion_transition(
+        &mut self,
+        state_id: StateId,
+        assertion: Assertion,
+        next_state_id: StateId,
+    ) {
+        let state = &mut self.states[state_id];
+        state.transitions.push(Transition {
+            byte_range: None,
+            assertion: Some(assertion),
             next_state_id,
         });
     }
@@ -74,22 +214,95 @@ pub type StateId = usize;
 #[derive(Debug)]
 pub struct State {
     pub matched_token: Option<usize>,
+    pub marker_token: Option<usize>,
     pub transitions: Vec<Transition>,
 }
 
 #[derive(Debug, Eq, Hash, PartialEq)]
 pub struct Transition {
     pub byte_range: Option<ByteRange>,
+    pub assertion: Option<Assertion>,
     pub next_state_id: StateId,
 }
 
 impl Transition {
     pub fn is_empty(&self) -> bool {
-        self.byte_range.is_none()
+        self.byte_range.is_none() && self.assertion.is_none()
     }
 }
 
-#[derive(Debug, Eq, Hash, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Assertion {
+    StartOfInput,
+    EndOfInput,
+    StartOfLine,
+    EndOfLine,
+    WordBoundary,
+    NotWordBoundary,
+}
+
+impl Assertion {
+    pub fn reverse(self) -> Self {
+        match self {
+            Self::StartOfInput => Self::EndOfInput,
+            Self::EndOfInput => Self::StartOfInput,
+            Self::StartOfLine => Self::EndOfLine,
+            Self::EndOfLine => Self::StartOfLine,
+            assertion => assertion,
+        }
+    }
+
+    pub fn is_satisfied(self, previous: ByteClass, next: Option<ByteClass>) -> bool {
+        match (self, next) {
+            (Self::StartOfInput, _) => previous == ByteClass::Boundary,
+            (Self::StartOfLine, _) => {
+                matches!(previous, ByteClass::Boundary | ByteClass::LineFeed)
+            }
+            (_, None) => false,
+            (Self::EndOfInput, Some(next)) => next == ByteClass::Boundary,
+            (Self::EndOfLine, Some(next)) => {
+                matches!(next, ByteClass::Boundary | ByteClass::LineFeed)
+            }
+            (Self::WordBoundary, Some(next)) => previous.is_word() != next.is_word(),
+            (Self::NotWordBoundary, Some(next)) => previous.is_word() == next.is_word(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum ByteClass {
+    #[default]
+    Boundary,
+    LineFeed,
+    Word,
+    Other,
+}
+
+impl ByteClass {
+    pub fn of(byte: Option<u8>) -> Self {
+        match byte {
+            None => Self::Boundary,
+            Some(b'\n') => Self::LineFeed,
+            Some(byte) if byte == b'_' || byte.is_ascii_alphanumeric() => Self::Word,
+            Some(_) => Self::Other,
+        }
+    }
+
+    pub fn is_word(self) -> bool {
+        self == Self::Word
+    }
+}
+
+pub const BYTE_CLASSES: [ByteClass; BYTE_CLASS_COUNT] = [
+    ByteClass::Boundary,
+    ByteClass::LineFeed,
+    ByteClass::Word,
+    ByteClass::Other,
+];
+
+pub const BYTE_CLASS_COUNT: usize = 4;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub struct ByteRange {
     pub start: u8,
     pub end: u8,
@@ -101,6 +314,18 @@ impl ByteRange {
     }
 }
 
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct CharRange {
+    pub start: char,
+    pub end: char,
+}
+
+impl CharRange {
+    pub fn contains(&self, ch: char) -> bool {
+        self.start <= ch && ch <= self.end
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 pub struct Fragment {
     pub start_state_id: StateId,
@@ -111,6 +336,8 @@ pub struct Fragment {
 pub struct Builder {
     nfa: Nfa,
     fragment_stack: Vec<Fragment>,
+    literal_start_state_id: Option<StateId>,
+    literal_transitions: HashMap<(StateId, u8), StateId>,
 }
 
 impl Builder {
@@ -119,20 +346,142 @@ impl Builder {
     }
 
     pub fn char(&mut self, ch: char) {
-        let mut bytes = [0; 4];
-        for &byte in ch.encode_utf8(&mut bytes).as_bytes() {
-            self.byte_range(ByteRange {
-                start: byte,
-                end: byte,
-            });
+        self.char_class(&[CharRange { start: ch, end: ch }]);
+    }
+
+    pub fn string(&mut self, string: &str) {
+        let start_state_id = self.nfa.add_state();
+        let mut state_id = start_state_id;
+        for &byte in string.as_bytes() {
+            let next_state_id = self.nfa.add_state();
+            self.nfa.add_transition(
+                state_id,
+                Some(ByteRange {
+                    start: byte,
+                    end: byte,
+                }),
+                next_state_id,
+            );
+            state_id = next_state_id;
         }
+        self.fragment_stack.push(Fragment {
+            start_state_id,
+            end_state_id: state_id,
+        })
+    }
+
+    pub fn literal(&mut self, bytes: &[u8], matched_token: usize) {
+        let start_state_id = match self.literal_start_state_id {
+            Some(start_state_id) => start_state_id,
+            None => {
+                let start_state_id = self.nfa.add_state();
+                self.literal_start_state_id = Some(start_state_id);
+                start_state_id
+            }
+        };
+        let mut state_id = start_state_id;
+        for (index, &byte) in bytes.iter().enumerate() {
+            let is_last = index + 1 == bytes.len();
+            // Passing through a node where an earlier literal ends would give this literal priority
+            // over that one, so the path is split there instead.
+            state_id = match self.literal_transitions.get(&(state_id, byte)) {
+                Some(&next_state_id) if is_last || !self.accepts_literal(next_state_id) => {
+                    next_state_id
+                }
+                _ => {
+                    let next_state_id = self.nfa.add_state();
+                    self.nfa.add_transition(
+                        state_id,
+                        Some(ByteRange {
+                            start: byte,
+                            end: byte,
+                        }),
+                        next_state_id,
+                    );
+                    self.literal_transitions
+                        .insert((state_id, byte), next_state_id);
+                    next_state_id
+                }
+            };
+        }
+        let end_state_id = self.nfa.add_state();
+        self.nfa.add_transition(state_id, None, end_state_id);
+        self.nfa.states[end_state_id].matched_token = Some(matched_token);
+        self.nfa.fragments.push(Fragment {
+            start_state_id,
+            end_state_id,
+        });
+    }
+
+    fn accepts_literal(&self, state_id: StateId) -> bool {
+        self.nfa.states[state_id]
+            .transitions
+            .iter()
+            .any(Transition::is_empty)
+    }
+
+    pub fn char_class(&mut self, char_ranges: &[CharRange]) {
+        let mut char_ranges = char_ranges.to_vec();
+        char_ranges.sort_unstable();
+        let mut merged_char_ranges: Vec<CharRange> = Vec::new();
+        for char_range in char_ranges {
+            match merged_char_ranges.last_mut() {
+                Some(last) if char_range.start as u32 <= last.end as u32 + 1 => {
+                    last.end = last.end.max(char_range.end)
+                }
+                _ => merged_char_ranges.push(char_range),
+            }
+        }
+        let start_state_id = self.nfa.add_state();
+        let end_state_id = self.nfa.add_state();
+        for char_range in merged_char_ranges {
+            for sequence in Utf8Sequences::new(char_range) {
+                let (&last_byte_range, byte_ranges) = sequence.split_last().unwrap();
+                let mut state_id = start_state_id;
+                for &byte_range in byte_ranges {
+                    let next_state_id = self.nfa.add_state();
+                    self.nfa
+                        .add_transition(state_id, Some(byte_range), next_state_id);
+                    state_id = next_state_id;
+                }
+                self.nfa
+                    .add_transition(state_id, Some(last_byte_range), end_state_id);
+            }
+        }
+        self.fragment_stack.push(Fragment {
+            start_state_id,
+            end_state_id,
+        })
+    }
+
+    pub fn byte(&mut self, byte: u8) {
+        self.byte_range(ByteRange {
+            start: byte,
+            end: byte,
+        });
     }
 
     fn byte_range(&mut self, byte_range: ByteRange) {
+        self.byte_class(&[byte_range]);
+    }
+
+    pub fn byte_class(&mut self, byte_ranges: &[ByteRange]) {
         let start_state_id = self.nfa.add_state();
         let end_state_id = self.nfa.add_state();
-        self.nfa
-            .add_transition(start_state_id, Some(byte_range), end_state_id);
+        for &byte_range in byte_ranges {
+            self.nfa
+                .add_transition(start_state_id, Some(byte_range), end_state_id);
+        }
+        self.fragment_stack.push(Fragment {
+            start_state_id,
+            end_state_id,
+        })
+    }
+
+    pub fn empty(&mut self) {
+        let start_state_id = self.nfa.add_state();
+        let end_state_id = self.nfa.add_state();
+        self.nfa.add_transition(start_state_id, None, end_state_id);
         self.fragment_stack.push(Fragment {
             start_state_id,
             end_state_id,
@@ -217,13 +566,125 @@ impl Builder {
         });
     }
 
+    pub fn assertion(&mut self, assertion: Assertion) {
+        let start_state_id = self.nfa.add_state();
+        let end_state_id = self.nfa.add_state();
+        self.nfa
+            .add_assertion_transition(start_state_id, assertion, end_state_id);
+        self.fragment_stack.push(Fragment {
+            start_state_id,
+            end_state_id,
+        })
+    }
+
+    pub fn trailing_context(&mut self, marker_token: usize) {
+        let fragment_1 = self.fragment_stack.pop().unwrap();
+        let fragment_0 = self.fragment_stack.pop().unwrap();
+        let marker_state_id = self.nfa.add_state();
+        self.nfa.states[marker_state_id].marker_token = Some(marker_token);
+        self.nfa
+            .add_transition(fragment_0.end_state_id, None, marker_state_id);
+        self.nfa
+            .add_transition(marker_state_id, None, fragment_1.start_state_id);
+        self.fragment_stack.push(Fragment {
+            start_state_id: fragment_0.start_state_id,
+            end_state_id: fragment_1.end_state_id,
+        })
+    }
+
+    pub fn accept_trailing_context(&mut self, matched_token: usize) {
+        let fragment = self.fragment_stack.pop().unwrap();
+        self.nfa.states[fragment.end_state_id].matched_token = Some(matched_token);
+        self.nfa.trailing_context_fragments.push(fragment);
+    }
+
     pub fn accept(&mut self, matched_token: usize) {
         let fragment = self.fragment_stack.pop().unwrap();
         self.nfa.states[fragment.end_state_id].matched_token = Some(matched_token);
         self.nfa.fragments.push(fragment);
+        self.literal_start_state_id = None;
+        self.literal_transitions.clear();
+    }
+
+    pub fn tokens_mut(&mut self) -> &mut TokenTable {
+        self.nfa.tokens_mut()
     }
 
     pub fn build(self) -> Nfa {
         self.nfa
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::determinizer::{Determinizer, MatchKind},
+    };
+
+    fn longest_match(
+        literals: &[(&str, usize)],
+        match_kind: MatchKind,
+        bytes: &[u8],
+    ) -> Option<(usize, usize)> {
+        let mut builder = Builder::new();
+        for &(literal, token) in literals {
+            builder.literal(literal.as_bytes(), token);
+        }
+        let dfa = Determinizer::new(&builder.build())
+            .match_kind(match_kind)
+            .determinize();
+        dfa.longest_match(bytes.iter().cloned())
+            .map(|(token, rest)| (token, bytes.len() - rest.len()))
+    }
+
+    #[test]
+    fn literals_keep_their_priority_under_leftmost_first() {
+        let kind = MatchKind::LeftmostFirst;
+        assert_eq!(
+            longest_match(&[("abc", 0), ("ab", 1)], kind, b"abc"),
+            Some((0, 3))
+        );
+        assert_eq!(
+            longest_match(&[("abc", 0), ("ab", 1)], kind, b"abd"),
+            Some((1, 2))
+        );
+        assert_eq!(
+            longest_match(&[("ab", 0), ("abc", 1)], kind, b"abc"),
+            Some((0, 2))
+        );
+        assert_eq!(
+            longest_match(&[("abc", 0), ("ab", 1), ("abcd", 2)], kind, b"abcd"),
+            Some((0, 3))
+        );
+        assert_eq!(
+            longest_match(&[("ab", 0), ("ab", 1)], kind, b"ab"),
+            Some((0, 2))
+        );
+    }
+
+    #[test]
+    fn literals_match_longest_under_leftmost_longest() {
+        let kind = MatchKind::LeftmostLongest;
+        assert_eq!(
+            longest_match(&[("ab", 0), ("abc", 1)], kind, b"abc"),
+            Some((1, 3))
+        );
+        assert_eq!(
+            longest_match(&[("ab", 0), ("abcd", 1), ("abce", 2)], kind, b"abce"),
+            Some((2, 4))
+        );
+        assert_eq!(
+            longest_match(&[("ab", 1), ("ab", 0)], kind, b"ab"),
+            Some((0, 2))
+        );
+    }
+
+    #[test]
+    fn literals_share_a_trie() {
+        let mut builder = Builder::new();
+        builder.literal(b"abcd", 0);
+        builder.literal(b"abce", 1);
+        assert_eq!(builder.build().state_count(), 5 + 1 + 1 + 1);
+    }
+}
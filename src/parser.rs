@@ -1,12 +1,22 @@
 use {
-    crate::nfa::{Builder, Nfa},
-    std::{iter::Peekable, str::Chars},
+    crate::{
+        ast::{Ast, AstKind, Class, Repetition, Span},
+        compiler::Compiler,
+        determinizer::MatchKind,
+        nfa::{Assertion, ByteRange, CharRange, Nfa},
+        simplifier,
+        token::TokenTable,
+        unicode,
+    },
+    std::{collections::HashMap, error, fmt, iter::Peekable, str::CharIndices},
 };
 
 #[derive(Debug, Default)]
 pub struct Parser {
-    nfa: Builder,
-    operator_stack: Vec<Operator>,
+    compiler: Compiler,
+    definitions: HashMap<String, Ast>,
+    rules: Vec<Rule>,
+    match_kind: MatchKind,
 }
 
 impl Parser {
@@ -14,78 +24,551 @@ impl Parser {
         Self::default()
     }
 
-    pub fn parse(&mut self, pattern: &str, token: usize) {
-        Parse {
-            builder: &mut self.nfa,
-            operator_stack: &mut self.operator_stack,
-            chars: pattern.chars().peekable(),
-            token,
+    pub fn define(&mut self, name: &str, pattern: &str) -> Result<(), Error> {
+        if !is_name(name) {
+            return Err(Error::new(ErrorKind::InvalidName, 0));
+        }
+        let ast = parse(pattern)?;
+        if let AstKind::TrailingContext(ast, _) = &ast.kind {
+            return Err(Error::new(ErrorKind::InvalidTrailingContext, ast.span.end));
+        }
+        self.definitions.insert(name.to_string(), ast);
+        Ok(())
+    }
+
+    pub fn parse(&mut self, pattern: &str, token: usize) -> Result<(), Error> {
+        let ast = self.expand(parse(pattern)?, &mut Vec::new())?;
+        self.rules.push(Rule::Pattern(ast, token));
+        Ok(())
+    }
+
+    pub fn literal(&mut self, text: &str, token: usize) {
+        self.rules.push(Rule::Literal(text.to_string(), token));
+    }
+
+    pub fn tokens_mut(&mut self) -> &mut TokenTable {
+        self.compiler.tokens_mut()
+    }
+
+    /// Sets the match kind the patterns are simplified for. Simplification happens in `build`, so
+    /// this can be called at any point before then.
+    pub fn set_match_kind(&mut self, match_kind: MatchKind) {
+        self.match_kind = match_kind;
+    }
+
+    pub fn build(mut self) -> Nfa {
+        for rule in self.rules {
+            match rule {
+                Rule::Pattern(ast, token) => self
+                    .compiler
+                    .compile(&simplifier::simplify(ast, self.match_kind), token),
+                Rule::Literal(text, token) => self.compiler.literal(text.as_bytes(), token),
+            }
         }
-        .parse()
+        self.compiler.build()
     }
 
-    pub fn build(self) -> Nfa {
-        self.nfa.build()
+    fn expand<'a>(&'a self, ast: Ast, names: &mut Vec<&'a str>) -> Result<Ast, Error> {
+        let span = ast.span;
+        let kind = match ast.kind {
+            AstKind::Reference(name) => {
+                let (name, definition) = self
+                    .definitions
+                    .get_key_value(&name)
+                    .ok_or_else(|| Error::new(ErrorKind::UndefinedName, span.start))?;
+                if names.contains(&name.as_str()) {
+                    return Err(Error::new(ErrorKind::CyclicDefinition, span.start));
+                }
+                names.push(name);
+                let ast = self
+                    .expand(definition.clone(), names)
+                    .map_err(|error| Error::new(error.kind, span.start))?;
+                names.pop();
+                AstKind::Group(Box::new(ast))
+            }
+            AstKind::Concatenate(asts) => AstKind::Concatenate(
+                asts.into_iter()
+                    .map(|ast| self.expand(ast, names))
+                    .collect::<Result<_, _>>()?,
+            ),
+            AstKind::Alternate(asts) => AstKind::Alternate(
+                asts.into_iter()
+                    .map(|ast| self.expand(ast, names))
+                    .collect::<Result<_, _>>()?,
+            ),
+            AstKind::Repeat(ast, repetition) => {
+                AstKind::Repeat(Box::new(self.expand(*ast, names)?), repetition)
+            }
+            AstKind::Group(ast) => AstKind::Group(Box::new(self.expand(*ast, names)?)),
+            AstKind::TrailingContext(ast, trailing_context) => AstKind::TrailingContext(
+                Box::new(self.expand(*ast, names)?),
+                Box::new(self.expand(*trailing_context, names)?),
+            ),
+            kind => kind,
+        };
+        Ok(Ast::new(kind, span))
+    }
+}
+
+#[derive(Debug)]
+enum Rule {
+    Pattern(Ast, usize),
+    Literal(String, usize),
+}
+
+pub fn parse(pattern: &str) -> Result<Ast, Error> {
+    Parse {
+        chars: pattern.char_indices().peekable(),
+        len: pattern.len(),
+        expects_operand: true,
+        flags: Flags::default(),
+        operand_stack: Vec::new(),
+        operator_stack: Vec::new(),
     }
+    .parse()
+}
+
+fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|ch| ch == '_' || ch.is_alphabetic())
+        && chars.all(|ch| ch == '_' || ch.is_alphanumeric())
+}
+
+fn escaped_char(ch: char) -> Option<char> {
+    match ch {
+        't' => Some('\t'),
+        'n' => Some('\n'),
+        'r' => Some('\r'),
+        ch if ch.is_ascii_punctuation() => Some(ch),
+        _ => None,
+    }
+}
+
+fn negate_bytes(byte_ranges: &[ByteRange]) -> Vec<ByteRange> {
+    let mut is_member = [false; 256];
+    for &ByteRange { start, end } in byte_ranges {
+        for byte in start..=end {
+            is_member[byte as usize] = true;
+        }
+    }
+    let mut negated_byte_ranges: Vec<ByteRange> = Vec::new();
+    for byte in (0..=u8::MAX).filter(|&byte| !is_member[byte as usize]) {
+        match negated_byte_ranges.last_mut() {
+            Some(byte_range) if byte_range.end + 1 == byte => byte_range.end = byte,
+            _ => negated_byte_ranges.push(ByteRange {
+                start: byte,
+                end: byte,
+            }),
+        }
+    }
+    negated_byte_ranges
 }
 
 struct Parse<'a> {
-    builder: &'a mut Builder,
-    operator_stack: &'a mut Vec<Operator>,
-    chars: Peekable<Chars<'a>>,
-    token: usize,
+    chars: Peekable<CharIndices<'a>>,
+    len: usize,
+    expects_operand: bool,
+    flags: Flags,
+    operand_stack: Vec<Ast>,
+    operator_stack: Vec<Operator>,
 }
 
 impl<'a> Parse<'a> {
-    fn parse(&mut self) {
-        while let Some(ch) = self.chars.next() {
+    fn parse(mut self) -> Result<Ast, Error> {
+        while let Some((position, ch)) = self.chars.next() {
             match ch {
-                '(' => self.operator_stack.push(Operator::LeftParenthesis),
+                '(' if self.peek() == Some(')') => {
+                    self.chars.next();
+                    self.push(AstKind::Empty, Span::new(position, position + 2));
+                }
+                '(' if self.peek() == Some('?') => {
+                    self.chars.next();
+                    let flags = self.parse_flags(position)?;
+                    match self.next(position)? {
+                        ')' => {
+                            self.flags = flags;
+                            if self.peek().is_none_or(|ch| ")/|".contains(ch)) {
+                                let span = Span::new(position, self.position());
+                                self.push(AstKind::Empty, span);
+                            }
+                        }
+                        ':' => {
+                            self.operator_stack
+                                .push(Operator::LeftParenthesis(self.flags, position));
+                            self.flags = flags;
+                        }
+                        _ => return Err(Error::new(ErrorKind::InvalidFlag, position)),
+                    }
+                }
+                '(' => {
+                    self.expect_operand(position)?;
+                    self.operator_stack
+                        .push(Operator::LeftParenthesis(self.flags, position))
+                }
                 ')' => {
-                    while let Some(operator) = self.operator_stack.pop() {
-                        if operator.is_left_parenthesis() {
-                            break;
+                    if self.expects_operand {
+                        return Err(Error::new(ErrorKind::MissingOperand, position));
+                    }
+                    loop {
+                        match self.operator_stack.pop() {
+                            Some(Operator::LeftParenthesis(flags, start)) => {
+                                self.flags = flags;
+                                let ast = self.operand_stack.pop().unwrap();
+                                self.push(
+                                    AstKind::Group(Box::new(ast)),
+                                    Span::new(start, position + 1),
+                                );
+                                break;
+                            }
+                            Some(operator) => self.apply_operator(operator),
+                            None => {
+                                return Err(Error::new(ErrorKind::UnbalancedParenthesis, position))
+                            }
                         }
-                        self.apply_operator(operator);
                     }
-                    self.try_concatenate();
                 }
-                '*' => {
-                    self.builder.zero_or_more();
-                    self.try_concatenate();
+                '*' | '+' | '?' => {
+                    if self.expects_operand {
+                        return Err(Error::new(ErrorKind::MissingOperand, position));
+                    }
+                    let repetition = match ch {
+                        '*' => Repetition::ZeroOrMore,
+                        '+' => Repetition::OneOrMore,
+                        _ => Repetition::ZeroOrOne,
+                    };
+                    let ast = self.operand_stack.pop().unwrap();
+                    let span = Span::new(ast.span.start, position + 1);
+                    self.push(AstKind::Repeat(Box::new(ast), repetition), span);
                 }
-                '+' => {
-                    self.builder.one_or_more();
-                    self.try_concatenate();
+                '[' => {
+                    self.expect_operand(position)?;
+                    let kind = self.parse_class(position)?;
+                    let span = Span::new(position, self.position());
+                    self.push(kind, span);
                 }
-                '?' => {
-                    self.builder.zero_or_one();
-                    self.try_concatenate();
+                '\\' => {
+                    self.expect_operand(position)?;
+                    let kind = match self.next(position)? {
+                        'x' => {
+                            let byte = self.parse_hex_byte(position)?;
+                            self.byte(byte)
+                        }
+                        ch @ ('p' | 'P') => {
+                            let char_ranges = self.parse_property(position, ch == 'P')?;
+                            self.char_class(char_ranges)
+                        }
+                        'A' => AstKind::Assertion(Assertion::StartOfInput),
+                        'z' => AstKind::Assertion(Assertion::EndOfInput),
+                        'b' => AstKind::Assertion(Assertion::WordBoundary),
+                        'B' => AstKind::Assertion(Assertion::NotWordBoundary),
+                        ch => {
+                            let ch = escaped_char(ch)
+                                .ok_or_else(|| Error::new(ErrorKind::InvalidEscape, position))?;
+                            self.char(ch)
+                        }
+                    };
+                    let span = Span::new(position, self.position());
+                    self.push(kind, span);
+                }
+                '^' | '$' => {
+                    self.expect_operand(position)?;
+                    let assertion = if ch == '^' {
+                        Assertion::StartOfLine
+                    } else {
+                        Assertion::EndOfLine
+                    };
+                    self.push(
+                        AstKind::Assertion(assertion),
+                        Span::new(position, position + 1),
+                    );
                 }
-                '\\' => match self.chars.next().unwrap() {
-                    '(' | ')' | '*' | '+' | '?' | '\\' | '|' => {
-                        self.builder.char(ch);
-                        self.try_concatenate();
+                '{' => {
+                    self.expect_operand(position)?;
+                    let name = self.parse_name(position)?;
+                    let span = Span::new(position, self.position());
+                    self.push(AstKind::Reference(name), span);
+                }
+                '|' => {
+                    if self.expects_operand {
+                        return Err(Error::new(ErrorKind::MissingOperand, position));
                     }
-                    _ => panic!(),
-                },
-                '|' => self.handle_operator(Operator::Alternate),
+                    self.handle_operator(Operator::Alternate);
+                    self.expects_operand = true;
+                }
+                '/' => {
+                    if self.expects_operand {
+                        return Err(Error::new(ErrorKind::MissingOperand, position));
+                    }
+                    if self.operator_stack.iter().any(|operator| {
+                        matches!(
+                            operator,
+                            Operator::LeftParenthesis(..) | Operator::TrailingContext
+                        )
+                    }) {
+                        return Err(Error::new(ErrorKind::InvalidTrailingContext, position));
+                    }
+                    self.handle_operator(Operator::TrailingContext);
+                    self.expects_operand = true;
+                }
                 ch => {
-                    self.builder.char(ch);
-                    self.try_concatenate();
+                    self.expect_operand(position)?;
+                    let kind = self.char(ch);
+                    self.push(kind, Span::new(position, position + ch.len_utf8()));
                 }
             }
         }
+        if self.expects_operand {
+            return Err(Error::new(ErrorKind::MissingOperand, self.len));
+        }
         while let Some(operator) = self.operator_stack.pop() {
-            assert!(!operator.is_left_parenthesis());
+            if operator.is_left_parenthesis() {
+                return Err(Error::new(ErrorKind::UnbalancedParenthesis, self.len));
+            }
             self.apply_operator(operator);
         }
-        self.builder.accept(self.token);
+        Ok(self.operand_stack.pop().unwrap())
+    }
+
+    fn parse_flags(&mut self, position: usize) -> Result<Flags, Error> {
+        let mut flags = self.flags;
+        let mut enable = true;
+        while let Some(ch) = self.peek() {
+            match ch {
+                'i' => flags.case_insensitive = enable,
+                '-' if enable => enable = false,
+                ')' | ':' => break,
+                _ => return Err(Error::new(ErrorKind::InvalidFlag, position)),
+            }
+            self.chars.next();
+        }
+        Ok(flags)
+    }
+
+    fn parse_class(&mut self, position: usize) -> Result<AstKind, Error> {
+        let mut byte_ranges = Vec::new();
+        let mut char_ranges = Vec::new();
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.chars.next();
+        }
+        loop {
+            let position = self.position();
+            if self.peek() == Some(']') {
+                self.chars.next();
+                break;
+            }
+            let start = match self.parse_class_item()? {
+                ClassItem::Class(class_char_ranges) => {
+                    char_ranges.extend(class_char_ranges);
+                    continue;
+                }
+                start => start,
+            };
+            let end = if self.peek() == Some('-') {
+                self.chars.next();
+                self.parse_class_item()?
+            } else {
+                start.clone()
+            };
+            match (start, end) {
+                (ClassItem::Byte(start), ClassItem::Byte(end)) if start <= end => {
+                    byte_ranges.push(ByteRange { start, end })
+                }
+                (ClassItem::Char(start), ClassItem::Char(end)) if start <= end => {
+                    char_ranges.push(CharRange { start, end })
+                }
+                (ClassItem::Byte(start), ClassItem::Char(end)) if start.is_ascii() => char_ranges
+                    .push(CharRange {
+                        start: start as char,
+                        end,
+                    }),
+                _ => return Err(Error::new(ErrorKind::InvalidRange, position)),
+            }
+        }
+        if self.flags.case_insensitive {
+            unicode::add_simple_case_folding(&mut char_ranges);
+            for index in 0..byte_ranges.len() {
+                let ByteRange { start, end } = byte_ranges[index];
+                for byte in (start..=end).filter(u8::is_ascii_alphabetic) {
+                    let byte = byte ^ 0x20;
+                    byte_ranges.push(ByteRange {
+                        start: byte,
+                        end: byte,
+                    });
+                }
+            }
+        }
+        let is_ascii = byte_ranges
+            .iter()
+            .all(|byte_range| byte_range.end.is_ascii());
+        if char_ranges.is_empty() && !(negated && is_ascii) {
+            return Ok(AstKind::Class(Class::Bytes(if negated {
+                negate_bytes(&byte_ranges)
+            } else {
+                byte_ranges
+            })));
+        }
+        if !is_ascii {
+            return Err(Error::new(ErrorKind::MixedClass, position));
+        }
+        for &ByteRange { start, end } in &byte_ranges {
+            char_ranges.push(CharRange {
+                start: start as char,
+                end: end as char,
+            });
+        }
+        if negated {
+            char_ranges = unicode::negate(&char_ranges);
+        }
+        Ok(AstKind::Class(Class::Chars(char_ranges)))
+    }
+
+    fn parse_class_item(&mut self) -> Result<ClassItem, Error> {
+        let position = self.position();
+        match self.next(position)? {
+            '\\' => match self.next(position)? {
+                'x' => Ok(ClassItem::Byte(self.parse_hex_byte(position)?)),
+                ch @ ('p' | 'P') => Ok(ClassItem::Class(self.parse_property(position, ch == 'P')?)),
+                ch => escaped_char(ch)
+                    .map(|ch| ClassItem::Byte(ch as u8))
+                    .ok_or_else(|| Error::new(ErrorKind::InvalidEscape, position)),
+            },
+            ch if ch.is_ascii() => Ok(ClassItem::Byte(ch as u8)),
+            ch => Ok(ClassItem::Char(ch)),
+        }
+    }
+
+    fn parse_property(&mut self, position: usize, negated: bool) -> Result<Vec<CharRange>, Error> {
+        let name = match self.next(position)? {
+            '{' => {
+                let mut name = String::new();
+                loop {
+                    match self.next(position)? {
+                        '}' => break name,
+                        ch => name.push(ch),
+                    }
+                }
+            }
+            ch => ch.to_string(),
+        };
+        let char_ranges = unicode::property(&name)
+            .ok_or_else(|| Error::new(ErrorKind::UnknownProperty, position))?;
+        Ok(if negated {
+            unicode::negate(&char_ranges)
+        } else {
+            char_ranges
+        })
     }
 
-    fn try_concatenate(&mut self) {
-        if self.chars.peek().map_or(false, |ch| !")*+?|".contains(*ch)) {
+    fn parse_name(&mut self, position: usize) -> Result<String, Error> {
+        let mut name = String::new();
+        loop {
+            match self.next(position)? {
+                '}' => break,
+                ch => name.push(ch),
+            }
+        }
+        if !is_name(&name) {
+            return Err(Error::new(ErrorKind::InvalidName, position));
+        }
+        Ok(name)
+    }
+
+    fn parse_hex_byte(&mut self, position: usize) -> Result<u8, Error> {
+        let mut byte = 0;
+        for _ in 0..2 {
+            let digit = self
+                .next(position)?
+                .to_digit(16)
+                .ok_or_else(|| Error::new(ErrorKind::InvalidEscape, position))?;
+            byte = byte * 16 + digit as u8;
+        }
+        Ok(byte)
+    }
+
+    fn char(&self, ch: char) -> AstKind {
+        let mut char_ranges = vec![CharRange { start: ch, end: ch }];
+        if self.flags.case_insensitive {
+            char_ranges.extend(
+                unicode::simple_case_folding(ch).map(|ch| CharRange { start: ch, end: ch }),
+            );
+        }
+        if char_ranges.len() == 1 {
+            AstKind::Literal(ch)
+        } else {
+            AstKind::Class(Class::Chars(char_ranges))
+        }
+    }
+
+    fn char_class(&self, mut char_ranges: Vec<CharRange>) -> AstKind {
+        if self.flags.case_insensitive {
+            unicode::add_simple_case_folding(&mut char_ranges);
+        }
+        AstKind::Class(Class::Chars(char_ranges))
+    }
+
+    fn byte(&self, byte: u8) -> AstKind {
+        if self.flags.case_insensitive && byte.is_ascii_alphabetic() {
+            AstKind::Class(Class::Bytes(vec![
+                ByteRange {
+                    start: byte,
+                    end: byte,
+                },
+                ByteRange {
+                    start: byte ^ 0x20,
+                    end: byte ^ 0x20,
+                },
+            ]))
+        } else {
+            AstKind::Byte(byte)
+        }
+    }
+
+    fn expect_operand(&self, position: usize) -> Result<(), Error> {
+        if self.expects_operand {
+            Ok(())
+        } else {
+            Err(Error::new(ErrorKind::UnexpectedOperand, position))
+        }
+    }
+
+    fn push(&mut self, kind: AstKind, span: Span) {
+        self.operand_stack.push(Ast::new(kind, span));
+        self.operand();
+    }
+
+    fn operand(&mut self) {
+        self.expects_operand = false;
+        if self.try_concatenate() {
+            self.expects_operand = true;
+        }
+    }
+
+    fn next(&mut self, position: usize) -> Result<char, Error> {
+        match self.chars.next() {
+            Some((_, ch)) => Ok(ch),
+            None => Err(Error::new(ErrorKind::UnexpectedEnd, position)),
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().map(|&(_, ch)| ch)
+    }
+
+    fn position(&mut self) -> usize {
+        self.chars
+            .peek()
+            .map_or(self.len, |&(position, _)| position)
+    }
+
+    fn try_concatenate(&mut self) -> bool {
+        if self.peek().is_some_and(|ch| !")*+/?|".contains(ch)) {
             self.handle_operator(Operator::Concatenate);
+            true
+        } else {
+            false
         }
     }
 
@@ -101,27 +584,44 @@ impl<'a> Parse<'a> {
     }
 
     fn apply_operator(&mut self, operator: Operator) {
-        match operator {
-            Operator::Alternate => self.builder.alternate(),
-            Operator::Concatenate => self.builder.concatenate(),
-            Operator::LeftParenthesis => panic!(),
-        }
+        let ast_1 = self.operand_stack.pop().unwrap();
+        let ast_0 = self.operand_stack.pop().unwrap();
+        let span = ast_0.span.to(ast_1.span);
+        let kind = match (operator, ast_0.kind) {
+            (Operator::Alternate, AstKind::Alternate(mut asts)) => {
+                asts.push(ast_1);
+                AstKind::Alternate(asts)
+            }
+            (Operator::Concatenate, AstKind::Concatenate(mut asts)) => {
+                asts.push(ast_1);
+                AstKind::Concatenate(asts)
+            }
+            (Operator::Alternate, kind) => {
+                AstKind::Alternate(vec![Ast::new(kind, ast_0.span), ast_1])
+            }
+            (Operator::Concatenate, kind) => {
+                AstKind::Concatenate(vec![Ast::new(kind, ast_0.span), ast_1])
+            }
+            (Operator::TrailingContext, kind) => {
+                AstKind::TrailingContext(Box::new(Ast::new(kind, ast_0.span)), Box::new(ast_1))
+            }
+            (Operator::LeftParenthesis(..), _) => panic!(),
+        };
+        self.operand_stack.push(Ast::new(kind, span));
     }
 }
 
 #[derive(Clone, Copy, Debug)]
 pub enum Operator {
-    LeftParenthesis,
+    LeftParenthesis(Flags, usize),
+    TrailingContext,
     Alternate,
     Concatenate,
 }
 
 impl Operator {
     fn is_left_parenthesis(self) -> bool {
-        match self {
-            Self::LeftParenthesis => true,
-            _ => false,
-        }
+        matches!(self, Self::LeftParenthesis(..))
     }
 
     fn groups_left(self, other: Self) -> bool {
@@ -130,9 +630,248 @@ impl Operator {
 
     fn precedence(self) -> u32 {
         match self {
-            Operator::Alternate => 0,
-            Operator::Concatenate => 1,
-            Operator::LeftParenthesis => panic!(),
+            Operator::TrailingContext => 0,
+            Operator::Alternate => 1,
+            Operator::Concatenate => 2,
+            Operator::LeftParenthesis(..) => panic!(),
         }
     }
 }
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Flags {
+    pub case_insensitive: bool,
+}
+
+#[derive(Clone, Debug)]
+enum ClassItem {
+    Byte(u8),
+    Char(char),
+    Class(Vec<CharRange>),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Error {
+    pub kind: ErrorKind,
+    pub position: usize,
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind, position: usize) -> Self {
+        Self { kind, position }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at position {}", self.kind, self.position)
+    }
+}
+
+impl error::Error for Error {}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ErrorKind {
+    UnexpectedEnd,
+    UnexpectedOperand,
+    MissingOperand,
+    UnbalancedParenthesis,
+    InvalidEscape,
+    InvalidRange,
+    MixedClass,
+    InvalidFlag,
+    UnknownProperty,
+    InvalidName,
+    UndefinedName,
+    CyclicDefinition,
+    InvalidTrailingContext,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::UnexpectedEnd => "unexpected end of pattern",
+            Self::UnexpectedOperand => "unexpected operand",
+            Self::MissingOperand => "missing operand",
+            Self::UnbalancedParenthesis => "unbalanced parenthesis",
+            Self::InvalidEscape => "invalid escape sequence",
+            Self::InvalidRange => "invalid class range",
+            Self::MixedClass => "class mixes raw bytes with non-ASCII characters",
+            Self::InvalidFlag => "invalid flag group",
+            Self::UnknownProperty => "unknown Unicode property",
+            Self::InvalidName => "invalid definition name",
+            Self::UndefinedName => "undefined definition name",
+            Self::CyclicDefinition => "cyclic definition",
+            Self::InvalidTrailingContext => {
+                "trailing context is only allowed once at the top level"
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::determinizer::Determinizer};
+
+    fn literal(ch: char, start: usize) -> Ast {
+        Ast::new(
+            AstKind::Literal(ch),
+            Span::new(start, start + ch.len_utf8()),
+        )
+    }
+
+    #[test]
+    fn parses_into_a_spanned_ast() {
+        assert_eq!(
+            parse("ab|c").unwrap(),
+            Ast::new(
+                AstKind::Alternate(vec![
+                    Ast::new(
+                        AstKind::Concatenate(vec![literal('a', 0), literal('b', 1)]),
+                        Span::new(0, 2)
+                    ),
+                    literal('c', 3),
+                ]),
+                Span::new(0, 4)
+            )
+        );
+    }
+
+    #[test]
+    fn displays_asts_as_patterns() {
+        for pattern in &["a|bc", "(ab)*c+", "[a-c]?d", "\\bx$", "a(b|c)/d", "{NAME}+"] {
+            let ast = parse(pattern).unwrap();
+            assert_eq!(ast.to_string(), *pattern);
+            assert_eq!(parse(&ast.to_string()).unwrap().to_string(), *pattern);
+        }
+    }
+
+    #[test]
+    fn reports_error_positions() {
+        let error = |pattern| parse(pattern).unwrap_err();
+        assert_eq!(error("a("), Error::new(ErrorKind::MissingOperand, 2));
+        assert_eq!(error("a)"), Error::new(ErrorKind::UnbalancedParenthesis, 1));
+        assert_eq!(error("*a"), Error::new(ErrorKind::MissingOperand, 0));
+        assert_eq!(error("[b-a]"), Error::new(ErrorKind::InvalidRange, 1));
+        assert_eq!(
+            error("\\p{Nope}"),
+            Error::new(ErrorKind::UnknownProperty, 0)
+        );
+        assert_eq!(
+            error("a/b/c"),
+            Error::new(ErrorKind::InvalidTrailingContext, 3)
+        );
+        assert_eq!(error("(?x)a"), Error::new(ErrorKind::InvalidFlag, 0));
+    }
+
+    #[test]
+    fn parses_escapes() {
+        let kind = |pattern| parse(pattern).unwrap().kind;
+        assert_eq!(kind("\\t"), AstKind::Literal('\t'));
+        assert_eq!(kind("\\n"), AstKind::Literal('\n'));
+        assert_eq!(kind("\\r"), AstKind::Literal('\r'));
+        assert_eq!(kind("\\."), AstKind::Literal('.'));
+        assert_eq!(kind("\\-"), AstKind::Literal('-'));
+        assert_eq!(
+            kind("[\\t\\.]"),
+            AstKind::Class(Class::Bytes(vec![
+                ByteRange {
+                    start: b'\t',
+                    end: b'\t'
+                },
+                ByteRange {
+                    start: b'.',
+                    end: b'.'
+                },
+            ]))
+        );
+        assert_eq!(
+            parse("\\q").unwrap_err(),
+            Error::new(ErrorKind::InvalidEscape, 0)
+        );
+        assert_eq!(
+            parse("[\\q]").unwrap_err(),
+            Error::new(ErrorKind::InvalidEscape, 1)
+        );
+    }
+
+    #[test]
+    fn parses_negated_classes() {
+        let matches = |pattern, haystack: &str| {
+            let mut parser = Parser::new();
+            parser.parse(pattern, 0).unwrap();
+            let dfa = Determinizer::new(&parser.build()).determinize();
+            dfa.longest_match(haystack.bytes())
+                .is_some_and(|(_, rest)| rest.count() == 0)
+        };
+        assert!(matches("[^a-c]", "d"));
+        assert!(matches("[^a-c]", "é"));
+        assert!(!matches("[^a-c]", "b"));
+        assert!(matches("[^a-c]", "^"));
+        assert!(matches("[a^]", "^"));
+        assert!(!matches("(?i)[^a]", "A"));
+        assert!(!matches("[^\\x80-\\xFF]", "é"));
+        assert_eq!(
+            parse("[^\\x80-\\xFF]").unwrap().kind,
+            AstKind::Class(Class::Bytes(vec![ByteRange {
+                start: 0x00,
+                end: 0x7F
+            }]))
+        );
+        assert_eq!(
+            parse("[^\\x00-\\x7F]").unwrap().kind,
+            AstKind::Class(Class::Chars(vec![CharRange {
+                start: '\u{80}',
+                end: char::MAX
+            }]))
+        );
+    }
+
+    #[test]
+    fn compiles_parsed_asts() {
+        let mut parser = Parser::new();
+        parser.parse("(ab)*c+", 0).unwrap();
+        let dfa = Determinizer::new(&parser.build()).determinize();
+        assert_eq!(dfa.shortest_string(Some(0)), Some(b"c".to_vec()));
+        assert!(dfa.longest_match(b"ababcc".iter().cloned()).is_some());
+        assert!(dfa.longest_match(b"aba".iter().cloned()).is_none());
+    }
+
+    #[test]
+    fn expands_definitions() {
+        let mut parser = Parser::new();
+        parser.define("DIGIT", "[0-9]").unwrap();
+        parser.define("NUMBER", "{DIGIT}+").unwrap();
+        parser.parse("{NUMBER}(,{NUMBER})?", 0).unwrap();
+        let dfa = Determinizer::new(&parser.build()).determinize();
+        assert_eq!(dfa.shortest_string(Some(0)), Some(b"0".to_vec()));
+        assert_eq!(
+            dfa.longest_match(b"12,345x".iter().cloned())
+                .map(|(_, rest)| rest.count()),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_definitions() {
+        let mut parser = Parser::new();
+        assert_eq!(
+            parser.define("1X", "a"),
+            Err(Error::new(ErrorKind::InvalidName, 0))
+        );
+        assert_eq!(
+            parser.define("X", "a/b"),
+            Err(Error::new(ErrorKind::InvalidTrailingContext, 1))
+        );
+        assert_eq!(
+            parser.parse("a{UNDEFINED}", 0),
+            Err(Error::new(ErrorKind::UndefinedName, 1))
+        );
+        parser.define("A", "x{B}").unwrap();
+        parser.define("B", "y{A}").unwrap();
+        assert_eq!(
+            parser.parse("z{A}", 0),
+            Err(Error::new(ErrorKind::CyclicDefinition, 1))
+        );
+    }
+}
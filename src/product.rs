@@ -0,0 +1,301 @@
+use {
+    crate::{
+        dfa::{self, Dfa, StateId, StateIdRepr},
+        nfa::{BYTE_CLASSES, BYTE_CLASS_COUNT},
+    },
+    std::collections::{hash_map::Entry, HashMap, VecDeque},
+};
+
+pub struct Product<'a, S, T, F> {
+    dfa_0: &'a Dfa<S>,
+    dfa_1: &'a Dfa<T>,
+    operation: Operation,
+    resolve: F,
+    dfa: Dfa,
+    state_ids_by_state: HashMap<(StateId, StateId), StateId>,
+    state_stack: Vec<(StateId, StateId)>,
+}
+
+impl<'a, S, T, F> Product<'a, S, T, F>
+where
+    S: StateIdRepr,
+    T: StateIdRepr,
+    F: FnMut(usize, usize) -> usize,
+{
+    pub fn new(dfa_0: &'a Dfa<S>, dfa_1: &'a Dfa<T>, operation: Operation, resolve: F) -> Self {
+        let mut dfa = Dfa::new();
+        *dfa.tokens_mut() = dfa_0.tokens().clone();
+        Self {
+            dfa_0,
+            dfa_1,
+            operation,
+            resolve,
+            dfa,
+            state_ids_by_state: HashMap::new(),
+            state_stack: Vec::new(),
+        }
+    }
+
+    pub fn build(mut self) -> Dfa {
+        for &previous in &BYTE_CLASSES {
+            let start_state_id = self.get_or_create_state((
+                self.dfa_0.start_state_id_for(previous),
+                self.dfa_1.start_state_id_for(previous),
+            ));
+            self.dfa.set_start_state_id(previous, start_state_id);
+        }
+        while let Some(state) = self.state_stack.pop() {
+            let state_id = self.state_ids_by_state[&state];
+            for byte in 0..=u8::MAX {
+                let next_state = (
+                    self.dfa_0.next_state_id(state.0, byte),
+                    self.dfa_1.next_state_id(state.1, byte),
+                );
+                let next_state_id = self.get_or_create_state(next_state);
+                self.dfa.add_transition(state_id, byte, next_state_id);
+            }
+        }
+        self.dfa
+    }
+
+    fn get_or_create_state(&mut self, state: (StateId, StateId)) -> StateId {
+        if self.operation.is_dead(state) {
+            return dfa::dead_state_id();
+        }
+        if let Some(&state_id) = self.state_ids_by_state.get(&state) {
+            return state_id;
+        }
+        let state_id = self.dfa.add_state(None);
+        let matched_token_by_class_0 = self.dfa_0.state(state.0).matched_token_by_class();
+        let matched_token_by_class_1 = self.dfa_1.state(state.1).matched_token_by_class();
+        for &next in &BYTE_CLASSES {
+            let matched_token = self.operation.matched_token(
+                matched_token_by_class_0[next as usize],
+                matched_token_by_class_1[next as usize],
+                &mut self.resolve,
+            );
+            self.dfa
+                .set_matched_tokens_before(state_id, next, matched_token.as_slice());
+        }
+        self.state_ids_by_state.insert(state, state_id);
+        self.state_stack.push(state);
+        state_id
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Operation {
+    Intersection,
+    Union,
+    Difference,
+}
+
+impl Operation {
+    fn is_dead(self, (state_id_0, state_id_1): (StateId, StateId)) -> bool {
+        let dead_state_id = dfa::dead_state_id();
+        match self {
+            Self::Intersection => state_id_0 == dead_state_id || state_id_1 == dead_state_id,
+            Self::Union => state_id_0 == dead_state_id && state_id_1 == dead_state_id,
+            Self::Difference => state_id_0 == dead_state_id,
+        }
+    }
+
+    fn matched_token<F>(
+        self,
+        matched_token_0: Option<usize>,
+        matched_token_1: Option<usize>,
+        resolve: &mut F,
+    ) -> Option<usize>
+    where
+        F: FnMut(usize, usize) -> usize,
+    {
+        match (self, matched_token_0, matched_token_1) {
+            (Self::Intersection, Some(token_0), Some(token_1))
+            | (Self::Union, Some(token_0), Some(token_1)) => Some(resolve(token_0, token_1)),
+            (Self::Union, token, None) | (Self::Union, None, token) => token,
+            (Self::Difference, token, None) => token,
+            _ => None,
+        }
+    }
+}
+
+/// Returns the shortest string on which the two DFAs disagree. Matches are compared for every
+/// previous and next byte class, so the DFAs may only disagree on it in some contexts.
+pub fn find_counterexample<S, T>(
+    dfa_0: &Dfa<S>,
+    dfa_1: &Dfa<T>,
+    relation: Relation,
+) -> Option<Vec<u8>>
+where
+    S: StateIdRepr,
+    T: StateIdRepr,
+{
+    let mut previous_states = HashMap::new();
+    let mut state_queue = VecDeque::new();
+    for &previous in &BYTE_CLASSES {
+        let start_state = (
+            dfa_0.start_state_id_for(previous),
+            dfa_1.start_state_id_for(previous),
+        );
+        if let Entry::Vacant(entry) = previous_states.entry(start_state) {
+            entry.insert(None);
+            state_queue.push_back(start_state);
+        }
+    }
+    while let Some(state) = state_queue.pop_front() {
+        let matched_token_by_class_0 = dfa_0.state(state.0).matched_token_by_class();
+        let matched_token_by_class_1 = dfa_1.state(state.1).matched_token_by_class();
+        if (0..BYTE_CLASS_COUNT).any(|next| {
+            !relation.holds(
+                matched_token_by_class_0[next],
+                matched_token_by_class_1[next],
+            )
+        }) {
+            let mut bytes = Vec::new();
+            let mut state = state;
+            while let Some((previous_state, byte)) = previous_states[&state] {
+                bytes.push(byte);
+                state = previous_state;
+            }
+            bytes.reverse();
+            return Some(bytes);
+        }
+        for byte in 0..=u8::MAX {
+            let next_state = (
+                dfa_0.next_state_id(state.0, byte),
+                dfa_1.next_state_id(state.1, byte),
+            );
+            if relation.is_trivial(next_state) || previous_states.contains_key(&next_state) {
+                continue;
+            }
+            previous_states.insert(next_state, Some((state, byte)));
+            state_queue.push_back(next_state);
+        }
+    }
+    None
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Relation {
+    Equivalence,
+    Inclusion,
+}
+
+impl Relation {
+    fn is_trivial(self, (state_id_0, state_id_1): (StateId, StateId)) -> bool {
+        let dead_state_id = dfa::dead_state_id();
+        match self {
+            Self::Equivalence => state_id_0 == dead_state_id && state_id_1 == dead_state_id,
+            Self::Inclusion => state_id_0 == dead_state_id,
+        }
+    }
+
+    fn holds(self, matched_token_0: Option<usize>, matched_token_1: Option<usize>) -> bool {
+        match self {
+            Self::Equivalence => matched_token_0 == matched_token_1,
+            Self::Inclusion => matched_token_0.is_none() || matched_token_0 == matched_token_1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{determinizer::Determinizer, parser::Parser},
+    };
+
+    fn dfa(pattern: &str, token: usize) -> Dfa {
+        let mut parser = Parser::new();
+        parser.parse(pattern, token).unwrap();
+        Determinizer::new(&parser.build()).determinize()
+    }
+
+    fn matched_token(dfa: &Dfa, bytes: &[u8]) -> Option<usize> {
+        let state_id = bytes.iter().fold(dfa::start_state_id(), |state_id, &byte| {
+            dfa.next_state_id(state_id, byte)
+        });
+        dfa.state(state_id).matched_token()
+    }
+
+    #[test]
+    fn intersection() {
+        let intersection =
+            dfa("(a|b|c|d)+", 0).intersection(&dfa("(a|b|c|1)+", 1), |token, _| token);
+        assert_eq!(matched_token(&intersection, b"abc"), Some(0));
+        assert_eq!(matched_token(&intersection, b"abd"), None);
+        assert_eq!(matched_token(&intersection, b"a1"), None);
+    }
+
+    #[test]
+    fn union_resolves_conflicting_tokens() {
+        let dfa = dfa("if", 0).union(&dfa("(e|f|i)+", 1), |token_0, token_1| token_0.min(token_1));
+        assert_eq!(matched_token(&dfa, b"if"), Some(0));
+        assert_eq!(matched_token(&dfa, b"iff"), Some(1));
+        assert_eq!(matched_token(&dfa, b""), None);
+    }
+
+    #[test]
+    fn difference() {
+        let dfa = dfa("(e|f|i|l|s)+", 0).difference(&dfa("if|else", 1));
+        assert_eq!(matched_token(&dfa, b"iff"), Some(0));
+        assert_eq!(matched_token(&dfa, b"if"), None);
+        assert_eq!(matched_token(&dfa, b"else"), None);
+    }
+
+    #[test]
+    fn equivalence_returns_shortest_counterexample() {
+        assert!(dfa("a(b|c)", 0).is_equivalent(&dfa("ab|ac", 0)).is_ok());
+        assert_eq!(
+            dfa("a+", 0).is_equivalent(&dfa("a|aa|aaa", 0)),
+            Err(b"aaaa".to_vec())
+        );
+        assert_eq!(
+            dfa("ab", 0).is_equivalent(&dfa("ab", 1)),
+            Err(b"ab".to_vec())
+        );
+    }
+
+    #[test]
+    fn products_keep_assertions() {
+        let intersection = dfa("[a-z]+\\b", 0).intersection(&dfa("ab|abc", 1), |token, _| token);
+        let longest_match = |previous, haystack: &[u8]| {
+            let (token, rest) =
+                intersection.longest_match_after(previous, haystack.iter().cloned())?;
+            Some((token, haystack.len() - rest.len()))
+        };
+        assert_eq!(longest_match(None, b"ab"), Some((0, 2)));
+        assert_eq!(longest_match(None, b"abc!"), Some((0, 3)));
+        assert_eq!(longest_match(None, b"abcd"), None);
+        let union = dfa("^a", 0).union(&dfa("b", 1), |token, _| token);
+        assert!(union
+            .longest_match_after(Some(b'x'), b"a".iter().cloned())
+            .is_none());
+        assert!(union
+            .longest_match_after(Some(b'\n'), b"a".iter().cloned())
+            .is_some());
+        assert!(union.is_equivalent(&dfa("b", 1)).is_err());
+    }
+
+    #[test]
+    fn equivalence_compares_every_context() {
+        assert!(dfa("a\\b", 0).is_equivalent(&dfa("a$", 0)).is_err());
+        assert!(dfa("a$", 0).is_subset_of(&dfa("a\\b", 0)).is_ok());
+        assert!(dfa("a\\b", 0).is_subset_of(&dfa("a$", 0)).is_err());
+        assert!(dfa("\\ba", 0).is_equivalent(&dfa("\\b(a)", 0)).is_ok());
+        assert_eq!(
+            dfa("^a", 0).is_equivalent(&dfa("\\Aa", 0)),
+            Err(b"a".to_vec())
+        );
+    }
+
+    #[test]
+    fn inclusion_returns_shortest_counterexample() {
+        assert!(dfa("a|aa", 0).is_subset_of(&dfa("a+", 0)).is_ok());
+        assert_eq!(
+            dfa("a+", 0).is_subset_of(&dfa("a|aa", 0)),
+            Err(b"aaa".to_vec())
+        );
+        assert_eq!(dfa("b|a", 0).is_subset_of(&dfa("c", 0)), Err(b"a".to_vec()));
+    }
+}
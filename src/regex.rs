@@ -0,0 +1,360 @@
+use crate::{
+    determinizer::{Determinizer, MatchKind},
+    dfa::Dfa,
+    error::Error,
+    nfa::Nfa,
+    parser::Parser,
+    searcher::{Match, Matches, Searcher},
+    spec::Spec,
+    token::TokenTable,
+};
+
+#[derive(Clone, Debug)]
+pub struct Regex {
+    lexer: Lexer,
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Self, Error> {
+        let mut builder = LexerBuilder::new();
+        builder.token(pattern, 0)?;
+        Ok(Self {
+            lexer: builder.build()?,
+        })
+    }
+
+    pub fn is_match(&self, haystack: &[u8]) -> bool {
+        self.lexer.is_match(haystack)
+    }
+
+    pub fn find(&self, haystack: &[u8]) -> Option<Match> {
+        self.lexer.find(haystack, 0)
+    }
+
+    pub fn find_iter<'a>(&'a self, haystack: &'a [u8]) -> Matches<'a> {
+        self.lexer.searcher().find_iter(haystack)
+    }
+
+    pub fn longest_match(&self, haystack: &[u8]) -> Option<Match> {
+        self.lexer.longest_match(haystack, 0)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct LexerBuilder {
+    parser: Parser,
+    state_limit: Option<usize>,
+    match_kind: MatchKind,
+}
+
+impl LexerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_spec(spec: &Spec, mode: usize) -> Result<Self, Error> {
+        Ok(Self {
+            parser: spec.parser(mode)?,
+            ..Self::default()
+        })
+    }
+
+    pub fn state_limit(mut self, state_limit: Option<usize>) -> Self {
+        self.state_limit = state_limit;
+        self
+    }
+
+    pub fn match_kind(mut self, match_kind: MatchKind) -> Self {
+        self.match_kind = match_kind;
+        self
+    }
+
+    pub fn define(&mut self, name: &str, pattern: &str) -> Result<(), Error> {
+        self.parser.define(name, pattern)?;
+        Ok(())
+    }
+
+    pub fn token(&mut self, pattern: &str, token: usize) -> Result<(), Error> {
+        self.parser.parse(pattern, token)?;
+        Ok(())
+    }
+
+    pub fn literal(&mut self, text: &str, token: usize) {
+        self.parser.literal(text, token);
+    }
+
+    pub fn tokens_mut(&mut self) -> &mut TokenTable {
+        self.parser.tokens_mut()
+    }
+
+    pub fn build(self) -> Result<Lexer, Error> {
+        let Self {
+            mut parser,
+            state_limit,
+            match_kind,
+        } = self;
+        parser.set_match_kind(match_kind);
+        let nfa = parser.build();
+        let determinize = |nfa: &Nfa, anchored, match_kind| -> Result<Dfa, Error> {
+            Ok(Determinizer::new(nfa)
+                .anchored(anchored)
+                .match_kind(match_kind)
+                .state_limit(state_limit)
+                .try_determinize()?
+                .minimize())
+        };
+        Ok(Lexer {
+            searcher: Searcher::from_dfas(
+                determinize(&nfa, true, match_kind)?,
+                determinize(&nfa, false, match_kind)?,
+                determinize(&nfa, false, MatchKind::All)?,
+                determinize(&nfa.reverse(), true, match_kind)?,
+                determinize(
+                    &nfa.reverse_trailing_contexts(),
+                    true,
+                    MatchKind::LeftmostLongest,
+                )?,
+            ),
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Lexer {
+    searcher: Searcher,
+}
+
+impl Lexer {
+    pub fn searcher(&self) -> &Searcher {
+        &self.searcher
+    }
+
+    pub fn tokens(&self) -> &TokenTable {
+        self.searcher.anchored_dfa().tokens()
+    }
+
+    pub fn is_match(&self, haystack: &[u8]) -> bool {
+        self.searcher.is_match(haystack)
+    }
+
+    pub fn find(&self, haystack: &[u8], start: usize) -> Option<Match> {
+        let mut start = start;
+        loop {
+            let match_ = self.searcher.find(haystack, start)?;
+            if !self.is_skipped(match_.token) {
+                return Some(match_);
+            }
+            start = match_.end.max(match_.start + 1);
+        }
+    }
+
+    /// Splits `haystack` into consecutive tokens from the start. Iteration stops at the first
+    /// position where no rule matches or only an empty match is possible.
+    pub fn find_iter<'a>(&'a self, haystack: &'a [u8]) -> LexerMatches<'a> {
+        LexerMatches {
+            lexer: self,
+            haystack,
+            start: 0,
+        }
+    }
+
+    /// Finds the longest token starting at `start`, consuming any skipped tokens before it.
+    /// Returns `None` if no rule matches or a skipped token matches the empty string.
+    pub fn longest_match(&self, haystack: &[u8], start: usize) -> Option<Match> {
+        let mut start = start;
+        loop {
+            let match_ = self.searcher.longest_match(haystack, start)?;
+            if !self.is_skipped(match_.token) {
+                return Some(match_);
+            }
+            if match_.start == match_.end {
+                return None;
+            }
+            start = match_.end;
+        }
+    }
+
+    fn is_skipped(&self, token: usize) -> bool {
+        self.tokens().get(token).is_some_and(|token| token.skip)
+    }
+}
+
+#[derive(Debug)]
+pub struct LexerMatches<'a> {
+    lexer: &'a Lexer,
+    haystack: &'a [u8],
+    start: usize,
+}
+
+impl<'a> Iterator for LexerMatches<'a> {
+    type Item = Match;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let match_ = self.lexer.longest_match(self.haystack, self.start)?;
+        if match_.start == match_.end {
+            return None;
+        }
+        self.start = match_.end;
+        Some(match_)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::token::Token};
+
+    fn lexer(patterns: &[&str], match_kind: MatchKind) -> Lexer {
+        let mut builder = LexerBuilder::new().match_kind(match_kind);
+        for (token, pattern) in patterns.iter().enumerate() {
+            builder.token(pattern, token).unwrap();
+        }
+        builder.build().unwrap()
+    }
+
+    fn tokens(lexer: &Lexer, haystack: &[u8]) -> Vec<(usize, usize, usize)> {
+        lexer
+            .find_iter(haystack)
+            .map(|match_| (match_.start, match_.end, match_.token))
+            .collect()
+    }
+
+    #[test]
+    fn regex_finds_matches() {
+        let regex = Regex::new("[0-9]+").unwrap();
+        assert!(regex.is_match(b"ab12"));
+        assert!(!regex.is_match(b"ab"));
+        assert_eq!(
+            regex.find(b"ab12c3"),
+            Some(Match {
+                start: 2,
+                end: 4,
+                token: 0
+            })
+        );
+        assert_eq!(
+            regex
+                .find_iter(b"ab12c3")
+                .map(|match_| (match_.start, match_.end))
+                .collect::<Vec<_>>(),
+            vec![(2, 4), (5, 6)]
+        );
+        assert_eq!(regex.longest_match(b"ab12"), None);
+        assert!(Regex::new("a(").is_err());
+    }
+
+    #[test]
+    fn regex_reports_empty_matches() {
+        let regex = Regex::new("a*").unwrap();
+        assert!(regex.is_match(b"b"));
+        assert_eq!(
+            regex.longest_match(b"b"),
+            Some(Match {
+                start: 0,
+                end: 0,
+                token: 0,
+            })
+        );
+        assert_eq!(
+            regex
+                .find_iter(b"baa")
+                .map(|match_| (match_.start, match_.end))
+                .collect::<Vec<_>>(),
+            vec![(0, 0), (1, 3), (3, 3)]
+        );
+    }
+
+    #[test]
+    fn lexer_tokenizes_contiguously_from_the_start() {
+        let lexer = lexer(&["(a|b)+", " +"], MatchKind::LeftmostLongest);
+        assert_eq!(
+            tokens(&lexer, b"ab ba"),
+            vec![(0, 2, 0), (2, 3, 1), (3, 5, 0)]
+        );
+        assert_eq!(tokens(&lexer, b"ab!ba"), vec![(0, 2, 0)]);
+        assert_eq!(tokens(&lexer, b"!ab"), vec![]);
+    }
+
+    #[test]
+    fn lexer_stops_at_an_empty_match() {
+        let lexer = lexer(&["a*"], MatchKind::LeftmostLongest);
+        assert_eq!(tokens(&lexer, b"aab"), vec![(0, 2, 0)]);
+    }
+
+    #[test]
+    fn lexer_consumes_skipped_tokens() {
+        let mut builder = LexerBuilder::new();
+        builder.token("[a-z]+", 0).unwrap();
+        builder.token("[ ]+", 1).unwrap();
+        builder.tokens_mut().insert(
+            1,
+            Token {
+                name: "SPACE".to_string(),
+                pattern: "[ ]+".to_string(),
+                priority: 1,
+                skip: true,
+            },
+        );
+        let lexer = builder.build().unwrap();
+        assert_eq!(
+            lexer
+                .find_iter(b"ab  cd")
+                .map(|match_| (match_.start, match_.end, match_.token))
+                .collect::<Vec<_>>(),
+            vec![(0, 2, 0), (4, 6, 0)]
+        );
+        assert_eq!(
+            lexer.find(b"  cd", 0),
+            Some(Match {
+                start: 2,
+                end: 4,
+                token: 0
+            })
+        );
+        assert_eq!(
+            lexer.longest_match(b"ab  cd", 2),
+            Some(Match {
+                start: 4,
+                end: 6,
+                token: 0
+            })
+        );
+        assert_eq!(lexer.longest_match(b"ab  ", 2), None);
+    }
+
+    #[test]
+    fn lexer_respects_the_match_kind() {
+        let ends = |match_kind| {
+            lexer(&["a|ab"], match_kind)
+                .find_iter(b"ab")
+                .map(|match_| match_.end)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ends(MatchKind::LeftmostLongest), vec![2]);
+        assert_eq!(ends(MatchKind::LeftmostFirst), vec![1]);
+        assert_eq!(ends(MatchKind::Shortest), vec![1]);
+    }
+
+    #[test]
+    fn lexer_reports_the_first_token_on_ties() {
+        let lexer = lexer(&["if", "[a-z]+", " +"], MatchKind::LeftmostLongest);
+        let tokens = lexer
+            .find_iter(b"if iff")
+            .map(|match_| match_.token)
+            .collect::<Vec<_>>();
+        assert_eq!(tokens, vec![0, 2, 1]);
+    }
+
+    #[test]
+    fn lexer_find_past_the_end_finds_nothing() {
+        let lexer = lexer(&["a"], MatchKind::LeftmostLongest);
+        assert_eq!(lexer.find(b"a", 5), None);
+        assert_eq!(lexer.longest_match(b"a", 5), None);
+    }
+
+    #[test]
+    fn state_limit_is_enforced() {
+        let mut builder = LexerBuilder::new().state_limit(Some(4));
+        builder.token("[ab]*a[ab][ab][ab]", 0).unwrap();
+        assert!(builder.build().is_err());
+    }
+}
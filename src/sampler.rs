@@ -0,0 +1,157 @@
+use crate::{
+    analysis,
+    dfa::{Dfa, StateIdRepr},
+};
+
+#[derive(Debug)]
+pub struct Sampler<'a, S> {
+    dfa: &'a Dfa<S>,
+    length: usize,
+    string_counts: Vec<Vec<u128>>,
+    rng: Rng,
+}
+
+impl<'a, S: StateIdRepr> Sampler<'a, S> {
+    pub fn new(dfa: &'a Dfa<S>, token: Option<usize>, length: usize, seed: u64) -> Option<Self> {
+        Some(Self {
+            dfa,
+            length,
+            string_counts: analysis::string_counts(dfa, token, length)?,
+            rng: Rng::new(seed),
+        })
+    }
+
+    pub fn string_count(&self) -> u128 {
+        self.string_counts[self.length][self.dfa.start_state_id_after(None)]
+    }
+
+    pub fn sample(&mut self) -> Option<Vec<u8>> {
+        let string_count = self.string_count();
+        if string_count == 0 {
+            return None;
+        }
+        let mut index = self.rng.next_u128_below(string_count);
+        let mut bytes = Vec::with_capacity(self.length);
+        let mut state_id = self.dfa.start_state_id_after(None);
+        for length in (0..self.length).rev() {
+            let string_counts = &self.string_counts[length];
+            for transition in self.dfa.state(state_id).transitions() {
+                let string_count = string_counts[transition.next_state_id];
+                if index < string_count {
+                    bytes.push(transition.byte);
+                    state_id = transition.next_state_id;
+                    break;
+                }
+                index -= string_count;
+            }
+        }
+        Some(bytes)
+    }
+}
+
+impl<'a, S: StateIdRepr> Iterator for Sampler<'a, S> {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.sample()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut value = self.state;
+        value = (value ^ (value >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        value = (value ^ (value >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        value ^ (value >> 31)
+    }
+
+    pub fn next_u128(&mut self) -> u128 {
+        (self.next_u64() as u128) << 64 | self.next_u64() as u128
+    }
+
+    pub fn next_u128_below(&mut self, bound: u128) -> u128 {
+        assert!(bound > 0);
+        let threshold = bound.wrapping_neg() % bound;
+        loop {
+            let value = self.next_u128();
+            if value >= threshold {
+                break value % bound;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{determinizer::Determinizer, parser::Parser},
+        std::collections::HashMap,
+    };
+
+    fn dfa(pattern: &str) -> Dfa {
+        let mut parser = Parser::new();
+        parser.parse(pattern, 0).unwrap();
+        Determinizer::new(&parser.build()).determinize()
+    }
+
+    #[test]
+    fn samples_are_deterministic_per_seed() {
+        let dfa = dfa("(a|b|c|d)+");
+        let samples_0 = dfa
+            .sampler(Some(0), 8, 42)
+            .unwrap()
+            .take(10)
+            .collect::<Vec<_>>();
+        let samples_1 = dfa
+            .sampler(Some(0), 8, 42)
+            .unwrap()
+            .take(10)
+            .collect::<Vec<_>>();
+        assert_eq!(samples_0, samples_1);
+    }
+
+    #[test]
+    fn samples_are_accepted_strings_of_the_given_length() {
+        let dfa = dfa("a(b|c)*d");
+        let sampler = dfa.sampler(Some(0), 5, 7).unwrap();
+        assert_eq!(sampler.string_count(), 8);
+        for bytes in sampler.take(100) {
+            assert_eq!(bytes.len(), 5);
+            assert!(dfa.strings(Some(0), 5).any(|string| string == bytes));
+        }
+        assert_eq!(dfa.sampler(Some(0), 1, 7).unwrap().sample(), None);
+    }
+
+    #[test]
+    fn samples_satisfy_assertions() {
+        let dfa = dfa("[a-z]\\B[a-z]|[a-z]\\b[0-9]|[0-9]\\b");
+        let sampler = dfa.sampler(Some(0), 2, 3).unwrap();
+        assert_eq!(sampler.string_count(), 26 * 26);
+        for bytes in sampler.take(100) {
+            assert!(bytes.iter().all(u8::is_ascii_lowercase));
+        }
+        assert_eq!(dfa.sampler(Some(0), 1, 3).unwrap().string_count(), 10);
+    }
+
+    #[test]
+    fn samples_are_roughly_uniform() {
+        let dfa = dfa("(a|b)(a|b)");
+        let mut counts = HashMap::new();
+        for bytes in dfa.sampler(Some(0), 2, 1).unwrap().take(4000) {
+            *counts.entry(bytes).or_insert(0) += 1;
+        }
+        assert_eq!(counts.len(), 4);
+        assert!(counts.values().all(|&count| 800 < count && count < 1200));
+    }
+}
@@ -0,0 +1,506 @@
+use crate::{
+    determinizer::{Determinizer, MatchKind},
+    dfa::{self, Dfa},
+    nfa::Nfa,
+};
+
+#[derive(Clone, Debug)]
+pub struct Searcher {
+    anchored_dfa: Dfa,
+    forward_dfa: Dfa,
+    overlapping_dfa: Dfa,
+    reverse_dfa: Dfa,
+    trailing_context_dfa: Dfa,
+}
+
+impl Searcher {
+    pub fn new(nfa: &Nfa) -> Self {
+        Self {
+            anchored_dfa: Determinizer::new(nfa).determinize(),
+            forward_dfa: Determinizer::new(nfa).anchored(false).determinize(),
+            overlapping_dfa: Determinizer::new(nfa)
+                .anchored(false)
+                .match_kind(MatchKind::All)
+                .determinize(),
+            reverse_dfa: Determinizer::new(&nfa.reverse()).determinize(),
+            trailing_context_dfa: Determinizer::new(&nfa.reverse_trailing_contexts()).determinize(),
+        }
+    }
+
+    pub fn from_dfas(
+        anchored_dfa: Dfa,
+        forward_dfa: Dfa,
+        overlapping_dfa: Dfa,
+        reverse_dfa: Dfa,
+        trailing_context_dfa: Dfa,
+    ) -> Self {
+        Self {
+            anchored_dfa,
+            forward_dfa,
+            overlapping_dfa,
+            reverse_dfa,
+            trailing_context_dfa,
+        }
+    }
+
+    pub fn anchored_dfa(&self) -> &Dfa {
+        &self.anchored_dfa
+    }
+
+    pub fn forward_dfa(&self) -> &Dfa {
+        &self.forward_dfa
+    }
+
+    pub fn overlapping_dfa(&self) -> &Dfa {
+        &self.overlapping_dfa
+    }
+
+    pub fn reverse_dfa(&self) -> &Dfa {
+        &self.reverse_dfa
+    }
+
+    pub fn trailing_context_dfa(&self) -> &Dfa {
+        &self.trailing_context_dfa
+    }
+
+    pub fn is_match(&self, haystack: &[u8]) -> bool {
+        self.earliest_end(haystack, 0).is_some()
+    }
+
+    /// Finds the leftmost-longest match at or after `start`. The forward DFA stops starting new
+    /// threads once one has matched, so its last match is where the leftmost match ends, and the
+    /// reverse DFA then finds where it starts. The anchored DFA picks the token of the match.
+    pub fn find(&self, haystack: &[u8], start: usize) -> Option<Match> {
+        if start > haystack.len() {
+            return None;
+        }
+        let end = self.leftmost_end(haystack, start)?;
+        let start = self.leftmost_start(haystack, start, end)?;
+        let token = self.matched_token(haystack, start, end)?;
+        Some(self.trim_trailing_context(haystack, Match { start, end, token }))
+    }
+
+    pub fn find_iter<'a>(&'a self, haystack: &'a [u8]) -> Matches<'a> {
+        Matches {
+            searcher: self,
+            haystack,
+            start: 0,
+        }
+    }
+
+    pub fn matching_tokens(&self, haystack: &[u8]) -> Vec<usize> {
+        self.overlapping_dfa
+            .matching_tokens(haystack.iter().cloned())
+    }
+
+    pub fn longest_match(&self, haystack: &[u8], start: usize) -> Option<Match> {
+        let (token, rest) = self.anchored_dfa.longest_match_after(
+            byte_before(haystack, start),
+            haystack.get(start..)?.iter().cloned(),
+        )?;
+        Some(self.trim_trailing_context(
+            haystack,
+            Match {
+                start,
+                end: haystack.len() - rest.len(),
+                token,
+            },
+        ))
+    }
+
+    fn trim_trailing_context(&self, haystack: &[u8], match_: Match) -> Match {
+        let mut trailing_context_starts = Vec::new();
+        let mut state_id = self
+            .trailing_context_dfa
+            .start_state_id_after(haystack.get(match_.end).cloned());
+        if self.is_trailing_context_match(state_id, haystack, match_.end, match_.token) {
+            trailing_context_starts.push(match_.end);
+        }
+        for index in (match_.start..match_.end).rev() {
+            state_id = self
+                .trailing_context_dfa
+                .next_state_id(state_id, haystack[index]);
+            if state_id == dfa::dead_state_id() {
+                break;
+            }
+            if self.is_trailing_context_match(state_id, haystack, index, match_.token) {
+                trailing_context_starts.push(index);
+            }
+        }
+        if trailing_context_starts.is_empty() {
+            return match_;
+        }
+        trailing_context_starts.reverse();
+        let is_marked = |state_id, index| {
+            self.anchored_dfa
+                .state(state_id)
+                .marker_tokens()
+                .contains(&match_.token)
+                && trailing_context_starts.binary_search(&index).is_ok()
+        };
+        let mut state_id = self
+            .anchored_dfa
+            .start_state_id_after(byte_before(haystack, match_.start));
+        let mut end = Some(match_.start).filter(|&index| is_marked(state_id, index));
+        for (index, &byte) in haystack[..match_.end].iter().enumerate().skip(match_.start) {
+            state_id = self.anchored_dfa.next_state_id(state_id, byte);
+            if state_id == dfa::dead_state_id() {
+                break;
+            }
+            if is_marked(state_id, index + 1) {
+                end = Some(index + 1);
+            }
+        }
+        Match {
+            end: end.unwrap_or(match_.end),
+            ..match_
+        }
+    }
+
+    fn is_trailing_context_match(
+        &self,
+        state_id: dfa::StateId,
+        haystack: &[u8],
+        index: usize,
+        token: usize,
+    ) -> bool {
+        self.trailing_context_dfa
+            .state(state_id)
+            .matched_tokens_before(byte_before(haystack, index))
+            .contains(&token)
+    }
+
+    fn earliest_end(&self, haystack: &[u8], start: usize) -> Option<usize> {
+        let mut state_id = self
+            .forward_dfa
+            .start_state_id_after(byte_before(haystack, start));
+        if self
+            .forward_dfa
+            .state(state_id)
+            .matched_token_before(haystack.get(start).cloned())
+            .is_some()
+        {
+            return Some(start);
+        }
+        for (index, &byte) in haystack.iter().enumerate().skip(start) {
+            state_id = self.forward_dfa.next_state_id(state_id, byte);
+            if state_id == dfa::dead_state_id() {
+                break;
+            }
+            if self
+                .forward_dfa
+                .state(state_id)
+                .matched_token_before(haystack.get(index + 1).cloned())
+                .is_some()
+            {
+                return Some(index + 1);
+            }
+        }
+        None
+    }
+
+    fn leftmost_end(&self, haystack: &[u8], start: usize) -> Option<usize> {
+        let mut leftmost_end = None;
+        let mut state_id = self
+            .forward_dfa
+            .start_state_id_after(byte_before(haystack, start));
+        if self
+            .forward_dfa
+            .state(state_id)
+            .matched_token_before(haystack.get(start).cloned())
+            .is_some()
+        {
+            leftmost_end = Some(start);
+        }
+        for (index, &byte) in haystack.iter().enumerate().skip(start) {
+            state_id = self.forward_dfa.next_state_id(state_id, byte);
+            if state_id == dfa::dead_state_id() {
+                break;
+            }
+            if self
+                .forward_dfa
+                .state(state_id)
+                .matched_token_before(haystack.get(index + 1).cloned())
+                .is_some()
+            {
+                leftmost_end = Some(index + 1);
+            }
+        }
+        leftmost_end
+    }
+
+    fn leftmost_start(&self, haystack: &[u8], start: usize, end: usize) -> Option<usize> {
+        let mut state_id = self
+            .reverse_dfa
+            .start_state_id_after(haystack.get(end).cloned());
+        let mut leftmost_start = self
+            .reverse_dfa
+            .state(state_id)
+            .matched_token_before(byte_before(haystack, end))
+            .map(|_| end);
+        for index in (start..end).rev() {
+            state_id = self.reverse_dfa.next_state_id(state_id, haystack[index]);
+            if state_id == dfa::dead_state_id() {
+                break;
+            }
+            if self
+                .reverse_dfa
+                .state(state_id)
+                .matched_token_before(byte_before(haystack, index))
+                .is_some()
+            {
+                leftmost_start = Some(index);
+            }
+        }
+        leftmost_start
+    }
+
+    fn matched_token(&self, haystack: &[u8], start: usize, end: usize) -> Option<usize> {
+        let mut state_id = self
+            .anchored_dfa
+            .start_state_id_after(byte_before(haystack, start));
+        for &byte in &haystack[start..end] {
+            state_id = self.anchored_dfa.next_state_id(state_id, byte);
+        }
+        self.anchored_dfa
+            .state(state_id)
+            .matched_token_before(haystack.get(end).cloned())
+    }
+}
+
+fn byte_before(haystack: &[u8], index: usize) -> Option<u8> {
+    index
+        .checked_sub(1)
+        .and_then(|index| haystack.get(index).cloned())
+}
+
+#[derive(Debug)]
+pub struct Matches<'a> {
+    searcher: &'a Searcher,
+    haystack: &'a [u8],
+    start: usize,
+}
+
+impl<'a> Iterator for Matches<'a> {
+    type Item = Match;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.start > self.haystack.len() {
+            return None;
+        }
+        let match_ = self.searcher.find(self.haystack, self.start)?;
+        self.start = if match_.start == match_.end {
+            match_.end + 1
+        } else {
+            match_.end
+        };
+        Some(match_)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Match {
+    pub start: usize,
+    pub end: usize,
+    pub token: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::parser::Parser};
+
+    fn searcher(patterns: &[&str]) -> Searcher {
+        let mut parser = Parser::new();
+        for (token, pattern) in patterns.iter().enumerate() {
+            parser.parse(pattern, token).unwrap();
+        }
+        Searcher::new(&parser.build())
+    }
+
+    fn find_all(searcher: &Searcher, haystack: &[u8]) -> Vec<(usize, usize, usize)> {
+        searcher
+            .find_iter(haystack)
+            .map(|match_| (match_.start, match_.end, match_.token))
+            .collect()
+    }
+
+    #[test]
+    fn find_reports_the_leftmost_longest_match() {
+        let searcher = searcher(&["abcd", "bc"]);
+        assert_eq!(
+            searcher.find(b"xabcd", 0),
+            Some(Match {
+                start: 1,
+                end: 5,
+                token: 0,
+            })
+        );
+        assert_eq!(
+            searcher.find(b"xabcx", 0),
+            Some(Match {
+                start: 2,
+                end: 4,
+                token: 1,
+            })
+        );
+        assert_eq!(searcher.find(b"xabcd", 3), None);
+    }
+
+    #[test]
+    fn find_prefers_the_earlier_start_over_the_earlier_end() {
+        assert_eq!(
+            find_all(&searcher(&["ab", "bcde"]), b"abcde"),
+            vec![(0, 2, 0)]
+        );
+        assert_eq!(
+            find_all(&searcher(&["abcde", "bc"]), b"abcdx abcde"),
+            vec![(1, 3, 1), (6, 11, 0)]
+        );
+    }
+
+    #[test]
+    fn find_takes_the_token_from_the_anchored_dfa() {
+        let searcher = searcher(&["a+", "b|ab"]);
+        assert_eq!(find_all(&searcher, b"xab aa"), vec![(1, 3, 1), (4, 6, 0)]);
+    }
+
+    #[test]
+    fn matching_tokens_reports_every_token_that_matches_somewhere() {
+        let searcher = searcher(&["a", "b", "ab", "c"]);
+        assert_eq!(searcher.matching_tokens(b"xab"), vec![0, 1, 2]);
+        assert_eq!(searcher.matching_tokens(b"x"), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn matching_tokens_includes_empty_matches() {
+        let searcher = searcher(&["x*", "y", "^$"]);
+        assert_eq!(searcher.matching_tokens(b""), vec![0, 2]);
+        assert_eq!(searcher.matching_tokens(b"y"), vec![0, 1]);
+    }
+
+    #[test]
+    fn out_of_range_starts_find_nothing() {
+        let searcher = searcher(&["a*"]);
+        assert_eq!(searcher.find(b"a", 5), None);
+        assert_eq!(searcher.longest_match(b"a", 5), None);
+        assert_eq!(
+            searcher.find(b"a", 1),
+            Some(Match {
+                start: 1,
+                end: 1,
+                token: 0
+            })
+        );
+    }
+
+    #[test]
+    fn trailing_context_is_matched_but_not_consumed() {
+        let searcher = searcher(&["ab/cd", "[a-z]+"]);
+        let matches = |haystack| {
+            searcher
+                .find_iter(haystack)
+                .map(|match_| (match_.start, match_.end, match_.token))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(matches(b"abcd"), vec![(0, 2, 0), (2, 4, 1)]);
+        assert_eq!(matches(b"abc d"), vec![(0, 3, 1), (4, 5, 1)]);
+        assert_eq!(
+            searcher.longest_match(b"xabcd", 1),
+            Some(Match {
+                start: 1,
+                end: 3,
+                token: 0
+            })
+        );
+    }
+
+    #[test]
+    fn trailing_context_may_overlap_the_head() {
+        let searcher = searcher(&["a+/a", "a"]);
+        assert_eq!(
+            searcher.find(b"aaaa", 0),
+            Some(Match {
+                start: 0,
+                end: 3,
+                token: 0
+            })
+        );
+        let searcher = self::searcher(&["a*/ab"]);
+        assert_eq!(
+            searcher.longest_match(b"aaab", 0),
+            Some(Match {
+                start: 0,
+                end: 2,
+                token: 0
+            })
+        );
+        assert_eq!(
+            searcher.longest_match(b"ab", 0),
+            Some(Match {
+                start: 0,
+                end: 0,
+                token: 0
+            })
+        );
+    }
+
+    #[test]
+    fn trailing_context_sees_assertions_after_the_match() {
+        let searcher = searcher(&["[0-9]+/[a-z]+$", "[0-9a-z]+"]);
+        assert_eq!(
+            searcher.longest_match(b"12ab", 0),
+            Some(Match {
+                start: 0,
+                end: 2,
+                token: 0
+            })
+        );
+        assert_eq!(
+            searcher.longest_match(b"12ab!", 0),
+            Some(Match {
+                start: 0,
+                end: 4,
+                token: 1
+            })
+        );
+    }
+
+    #[test]
+    fn assertions_see_the_surrounding_haystack() {
+        let spans = |patterns: &[&str], haystack: &[u8]| {
+            self::searcher(patterns)
+                .find_iter(haystack)
+                .map(|match_| (match_.start, match_.end))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(spans(&["^[a-z]+"], b"ab cd\nef"), vec![(0, 2), (6, 8)]);
+        assert_eq!(spans(&["[a-z]+$"], b"ab cd\nef"), vec![(3, 5), (6, 8)]);
+        assert_eq!(spans(&["\\A[a-z]+"], b"ab\ncd"), vec![(0, 2)]);
+        assert_eq!(spans(&["[a-z]+\\z"], b"ab\ncd"), vec![(3, 5)]);
+        assert_eq!(
+            spans(&["\\bcat\\b"], b"cat concat cat_ cat."),
+            vec![(0, 3), (16, 19)]
+        );
+        assert_eq!(spans(&["\\Bcat"], b"cat concat"), vec![(7, 10)]);
+        assert_eq!(spans(&["^$"], b"a\n\nb"), vec![(2, 2)]);
+    }
+
+    #[test]
+    fn assertions_see_bytes_before_the_start_position() {
+        let searcher = searcher(&["\\b[a-z]+", "^[0-9]+"]);
+        assert_eq!(
+            searcher.find(b"xyz abc", 1),
+            Some(Match {
+                start: 4,
+                end: 7,
+                token: 0
+            })
+        );
+        assert_eq!(searcher.longest_match(b"xyz", 1), None);
+        assert_eq!(
+            searcher.longest_match(b"a\n12", 2).map(|m| m.token),
+            Some(1)
+        );
+        assert_eq!(searcher.longest_match(b"a 12", 2), None);
+    }
+}
@@ -0,0 +1,200 @@
+use {
+    crate::token::{Token, TokenTable},
+    std::{convert::TryFrom, error, fmt},
+};
+
+const MAGIC: &[u8; 8] = b"fsa-dfa\0";
+
+const VERSION: u32 = 2;
+
+#[derive(Debug, Default)]
+pub(crate) struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    pub fn new() -> Self {
+        let mut writer = Self::default();
+        writer.bytes.extend_from_slice(MAGIC);
+        writer.u32(VERSION);
+        writer
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn usize(&mut self, value: usize) {
+        self.bytes.extend_from_slice(&(value as u64).to_le_bytes());
+    }
+
+    /// Writes the low `width` bytes of `value`, for state ids stored in a type of that width.
+    pub fn uint(&mut self, value: usize, width: usize) {
+        self.bytes
+            .extend_from_slice(&(value as u64).to_le_bytes()[..width]);
+    }
+
+    pub fn usizes(&mut self, values: &[usize]) {
+        self.usize(values.len());
+        for &value in values {
+            self.usize(value);
+        }
+    }
+
+    pub fn str(&mut self, value: &str) {
+        self.usize(value.len());
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+
+    pub fn tokens(&mut self, tokens: &TokenTable) {
+        self.usize(tokens.iter().count());
+        for (token_id, token) in tokens.iter() {
+            self.usize(token_id);
+            self.str(&token.name);
+            self.str(&token.pattern);
+            self.usize(token.priority);
+            self.bool(token.skip);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Result<Self, Error> {
+        let mut reader = Self { bytes };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(Error::InvalidHeader);
+        }
+        let version = reader.u32()?;
+        if version != VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        Ok(reader)
+    }
+
+    pub fn finish(self) -> Result<(), Error> {
+        if self.bytes.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::TrailingBytes)
+        }
+    }
+
+    pub fn bool(&mut self) -> Result<bool, Error> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            value => Err(Error::InvalidValue(value as u64)),
+        }
+    }
+
+    pub fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u32(&mut self) -> Result<u32, Error> {
+        Ok(self.u64(4)? as u32)
+    }
+
+    pub fn usize(&mut self) -> Result<usize, Error> {
+        self.uint(8)
+    }
+
+    pub fn uint(&mut self, width: usize) -> Result<usize, Error> {
+        let value = self.u64(width)?;
+        usize::try_from(value).map_err(|_| Error::InvalidValue(value))
+    }
+
+    pub fn usizes(&mut self) -> Result<Vec<usize>, Error> {
+        let len = self.usize()?;
+        (0..len).map(|_| self.usize()).collect()
+    }
+
+    pub fn string(&mut self) -> Result<String, Error> {
+        let len = self.usize()?;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| Error::InvalidUtf8)
+    }
+
+    pub fn tokens(&mut self) -> Result<TokenTable, Error> {
+        let mut tokens = TokenTable::new();
+        for _ in 0..self.usize()? {
+            let token_id = self.usize()?;
+            if tokens.get(token_id).is_some() {
+                return Err(Error::InvalidToken(token_id));
+            }
+            let token = Token {
+                name: self.string()?,
+                pattern: self.string()?,
+                priority: self.usize()?,
+                skip: self.bool()?,
+            };
+            tokens.insert(token_id, token);
+        }
+        Ok(tokens)
+    }
+
+    fn u64(&mut self, width: usize) -> Result<u64, Error> {
+        let mut bytes = [0; 8];
+        bytes[..width].copy_from_slice(self.take(width)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.bytes.len() < len {
+            return Err(Error::UnexpectedEnd);
+        }
+        let (bytes, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(bytes)
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Error {
+    UnexpectedEnd,
+    TrailingBytes,
+    InvalidHeader,
+    UnsupportedVersion(u32),
+    StateIdWidth(usize),
+    InvalidValue(u64),
+    InvalidStateId(usize),
+    InvalidToken(usize),
+    InvalidUtf8,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnexpectedEnd => write!(f, "unexpected end of input"),
+            Self::TrailingBytes => write!(f, "trailing bytes after the DFA"),
+            Self::InvalidHeader => write!(f, "not a serialized DFA"),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "unsupported format version {}", version)
+            }
+            Self::StateIdWidth(width) => write!(f, "state ids are stored in {} bytes", width),
+            Self::InvalidValue(value) => write!(f, "invalid value {}", value),
+            Self::InvalidStateId(state_id) => write!(f, "invalid state id {}", state_id),
+            Self::InvalidToken(token) => write!(f, "invalid token {}", token),
+            Self::InvalidUtf8 => write!(f, "invalid UTF-8 in a token name or pattern"),
+        }
+    }
+}
+
+impl error::Error for Error {}
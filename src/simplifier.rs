@@ -0,0 +1,454 @@
+use crate::{
+    ast::{Ast, AstKind, Class, Repetition, Span},
+    determinizer::MatchKind,
+    nfa::{ByteRange, CharRange},
+};
+
+/// Simplifies `ast` without changing what it matches under `match_kind`. Under
+/// `MatchKind::LeftmostFirst` the order of alternatives decides which one wins, so alternatives
+/// are never factored in a way that would let a later one take priority.
+pub fn simplify(ast: Ast, match_kind: MatchKind) -> Ast {
+    merge_literals(rewrite(ast, match_kind == MatchKind::LeftmostFirst))
+}
+
+fn rewrite(ast: Ast, preserves_priority: bool) -> Ast {
+    let span = ast.span;
+    let rewrite = |ast| rewrite(ast, preserves_priority);
+    match ast.kind {
+        AstKind::Group(ast) => rewrite(*ast),
+        AstKind::Repeat(ast, repetition) => repeat(rewrite(*ast), repetition, span),
+        AstKind::Concatenate(asts) => concatenate(asts.into_iter().map(rewrite).collect(), span),
+        AstKind::Alternate(asts) => alternate(
+            asts.into_iter().map(rewrite).collect(),
+            span,
+            preserves_priority,
+        ),
+        AstKind::TrailingContext(ast, trailing_context) => Ast::new(
+            AstKind::TrailingContext(
+                Box::new(rewrite(*ast)),
+                Box::new(rewrite(*trailing_context)),
+            ),
+            span,
+        ),
+        kind => Ast::new(kind, span),
+    }
+}
+
+fn merge_literals(ast: Ast) -> Ast {
+    let span = ast.span;
+    let kind = match ast.kind {
+        AstKind::Repeat(ast, repetition) => {
+            AstKind::Repeat(Box::new(merge_literals(*ast)), repetition)
+        }
+        AstKind::Alternate(asts) => {
+            AstKind::Alternate(asts.into_iter().map(merge_literals).collect())
+        }
+        AstKind::TrailingContext(ast, trailing_context) => AstKind::TrailingContext(
+            Box::new(merge_literals(*ast)),
+            Box::new(merge_literals(*trailing_context)),
+        ),
+        AstKind::Concatenate(asts) => {
+            let mut merged_asts: Vec<Ast> = Vec::new();
+            for ast in asts.into_iter().map(merge_literals) {
+                let ch = match ast.kind {
+                    AstKind::Literal(ch) => ch,
+                    kind => {
+                        merged_asts.push(Ast::new(kind, ast.span));
+                        continue;
+                    }
+                };
+                match merged_asts.last_mut() {
+                    Some(Ast {
+                        kind: AstKind::String(string),
+                        span,
+                    }) => {
+                        string.push(ch);
+                        span.end = ast.span.end;
+                    }
+                    Some(Ast {
+                        kind: AstKind::Literal(previous_ch),
+                        span,
+                    }) => {
+                        *span = span.to(ast.span);
+                        let string = [*previous_ch, ch].iter().collect();
+                        merged_asts.last_mut().unwrap().kind = AstKind::String(string);
+                    }
+                    _ => merged_asts.push(Ast::new(AstKind::Literal(ch), ast.span)),
+                }
+            }
+            match merged_asts.len() {
+                1 => return merged_asts.pop().unwrap(),
+                _ => AstKind::Concatenate(merged_asts),
+            }
+        }
+        kind => kind,
+    };
+    Ast::new(kind, span)
+}
+
+fn repeat(ast: Ast, repetition: Repetition, span: Span) -> Ast {
+    match ast.kind {
+        AstKind::Empty => Ast::new(AstKind::Empty, span),
+        AstKind::Repeat(ast, inner_repetition) => {
+            let repetition = if inner_repetition == repetition {
+                repetition
+            } else {
+                Repetition::ZeroOrMore
+            };
+            Ast::new(AstKind::Repeat(ast, repetition), span)
+        }
+        kind => Ast::new(
+            AstKind::Repeat(Box::new(Ast::new(kind, ast.span)), repetition),
+            span,
+        ),
+    }
+}
+
+fn concatenate(asts: Vec<Ast>, span: Span) -> Ast {
+    let mut concatenated_asts = flatten(asts, |kind| match kind {
+        AstKind::Concatenate(asts) => Ok(asts),
+        kind => Err(kind),
+    });
+    concatenated_asts.retain(|ast| ast.kind != AstKind::Empty);
+    match concatenated_asts.len() {
+        0 => Ast::new(AstKind::Empty, span),
+        1 => concatenated_asts.pop().unwrap(),
+        _ => Ast::new(AstKind::Concatenate(concatenated_asts), span),
+    }
+}
+
+fn alternate(asts: Vec<Ast>, span: Span, preserves_priority: bool) -> Ast {
+    let mut asts = flatten(asts, |kind| match kind {
+        AstKind::Alternate(asts) => Ok(asts),
+        kind => Err(kind),
+    });
+    if let Some(index) = asts.iter().position(|ast| ast.kind == AstKind::Empty) {
+        let mut index = index + 1;
+        while index < asts.len() {
+            if asts[index].kind == AstKind::Empty {
+                asts.remove(index);
+            } else {
+                index += 1;
+            }
+        }
+    }
+    let mut asts = merge_classes(factor_prefixes(asts, preserves_priority));
+    let is_optional = if preserves_priority {
+        asts.last().is_some_and(|ast| ast.kind == AstKind::Empty)
+    } else {
+        asts.iter().any(|ast| ast.kind == AstKind::Empty)
+    };
+    if asts.len() > 1 && is_optional {
+        asts.retain(|ast| ast.kind != AstKind::Empty);
+        let ast = match asts.len() {
+            1 => asts.pop().unwrap(),
+            _ => {
+                let span = span_of(&asts).unwrap();
+                Ast::new(AstKind::Alternate(asts), span)
+            }
+        };
+        return repeat(ast, Repetition::ZeroOrOne, span);
+    }
+    match asts.len() {
+        1 => asts.pop().unwrap(),
+        _ => Ast::new(AstKind::Alternate(asts), span),
+    }
+}
+
+fn factor_prefixes(asts: Vec<Ast>, preserves_priority: bool) -> Vec<Ast> {
+    let mut sequences = asts.into_iter().map(sequence).collect::<Vec<_>>();
+    let mut factored_asts = Vec::new();
+    while !sequences.is_empty() {
+        let run_len = sequences
+            .iter()
+            .skip(1)
+            .take_while(|sequence| {
+                is_factorable(sequence.first())
+                    && kind(sequence.first()) == kind(sequences[0].first())
+            })
+            .count()
+            + 1;
+        let mut run = sequences.drain(..run_len).collect::<Vec<_>>();
+        if run_len == 1 || !is_factorable(run[0].first()) {
+            factored_asts.extend(run.into_iter().map(concatenate_sequence));
+            continue;
+        }
+        let prefix_len = (1..)
+            .take_while(|&index| {
+                is_factorable(run[0].get(index))
+                    && run
+                        .iter()
+                        .all(|sequence| kind(sequence.get(index)) == kind(run[0].get(index)))
+            })
+            .count()
+            + 1;
+        let mut prefix = run[0][..prefix_len].to_vec();
+        let suffixes = run
+            .iter_mut()
+            .map(|sequence| {
+                let suffix = sequence.split_off(prefix_len);
+                concatenate_sequence_or_empty(suffix, sequence.last().unwrap().span)
+            })
+            .collect::<Vec<_>>();
+        if preserves_priority
+            && suffixes[..suffixes.len() - 1]
+                .iter()
+                .any(|suffix| suffix.kind == AstKind::Empty)
+        {
+            factored_asts.extend(run.into_iter().zip(suffixes).map(|(mut sequence, suffix)| {
+                sequence.push(suffix);
+                concatenate_sequence(sequence)
+            }));
+            continue;
+        }
+        let suffix_span = span_of(&suffixes).unwrap();
+        prefix.push(alternate(suffixes, suffix_span, preserves_priority));
+        let span = span_of(&prefix).unwrap();
+        factored_asts.push(concatenate(prefix, span));
+    }
+    factored_asts
+}
+
+fn sequence(ast: Ast) -> Vec<Ast> {
+    match ast.kind {
+        AstKind::Concatenate(asts) => asts,
+        kind => vec![Ast::new(kind, ast.span)],
+    }
+}
+
+fn concatenate_sequence(sequence: Vec<Ast>) -> Ast {
+    let span = span_of(&sequence).unwrap();
+    concatenate(sequence, span)
+}
+
+fn concatenate_sequence_or_empty(sequence: Vec<Ast>, span: Span) -> Ast {
+    if sequence.is_empty() {
+        Ast::new(AstKind::Empty, Span::new(span.end, span.end))
+    } else {
+        concatenate_sequence(sequence)
+    }
+}
+
+fn kind(ast: Option<&Ast>) -> Option<&AstKind> {
+    ast.map(|ast| &ast.kind)
+}
+
+fn is_factorable(ast: Option<&Ast>) -> bool {
+    ast.is_some_and(|ast| {
+        matches!(
+            ast.kind,
+            AstKind::Literal(_) | AstKind::Byte(_) | AstKind::Class(_)
+        )
+    })
+}
+
+fn merge_classes(asts: Vec<Ast>) -> Vec<Ast> {
+    let mut merged_asts = Vec::new();
+    let mut index = 0;
+    while index < asts.len() {
+        let char_run_len = asts[index..]
+            .iter()
+            .take_while(|ast| char_ranges(ast).is_some())
+            .count();
+        let byte_run_len = asts[index..]
+            .iter()
+            .take_while(|ast| byte_ranges(ast).is_some())
+            .count();
+        let run_len = char_run_len.max(byte_run_len);
+        if run_len < 2 {
+            merged_asts.push(asts[index].clone());
+            index += 1;
+            continue;
+        }
+        let run = &asts[index..index + run_len];
+        let span = span_of(run).unwrap();
+        let kind = if char_run_len >= byte_run_len {
+            char_class(
+                run.iter()
+                    .flat_map(|ast| char_ranges(ast).unwrap())
+                    .collect(),
+            )
+        } else {
+            byte_class(
+                run.iter()
+                    .flat_map(|ast| byte_ranges(ast).unwrap())
+                    .collect(),
+            )
+        };
+        merged_asts.push(Ast::new(kind, span));
+        index += run_len;
+    }
+    merged_asts
+}
+
+fn char_ranges(ast: &Ast) -> Option<Vec<CharRange>> {
+    match &ast.kind {
+        AstKind::Literal(ch) => Some(vec![CharRange {
+            start: *ch,
+            end: *ch,
+        }]),
+        AstKind::Byte(byte) if byte.is_ascii() => Some(vec![CharRange {
+            start: *byte as char,
+            end: *byte as char,
+        }]),
+        AstKind::Class(Class::Chars(char_ranges)) => Some(char_ranges.clone()),
+        AstKind::Class(Class::Bytes(byte_ranges))
+            if byte_ranges
+                .iter()
+                .all(|byte_range| byte_range.end.is_ascii()) =>
+        {
+            Some(
+                byte_ranges
+                    .iter()
+                    .map(|byte_range| CharRange {
+                        start: byte_range.start as char,
+                        end: byte_range.end as char,
+                    })
+                    .collect(),
+            )
+        }
+        _ => None,
+    }
+}
+
+fn byte_ranges(ast: &Ast) -> Option<Vec<ByteRange>> {
+    match &ast.kind {
+        AstKind::Literal(ch) if ch.is_ascii() => Some(vec![ByteRange {
+            start: *ch as u8,
+            end: *ch as u8,
+        }]),
+        AstKind::Byte(byte) => Some(vec![ByteRange {
+            start: *byte,
+            end: *byte,
+        }]),
+        AstKind::Class(Class::Bytes(byte_ranges)) => Some(byte_ranges.clone()),
+        AstKind::Class(Class::Chars(char_ranges))
+            if char_ranges
+                .iter()
+                .all(|char_range| char_range.end.is_ascii()) =>
+        {
+            Some(
+                char_ranges
+                    .iter()
+                    .map(|char_range| ByteRange {
+                        start: char_range.start as u8,
+                        end: char_range.end as u8,
+                    })
+                    .collect(),
+            )
+        }
+        _ => None,
+    }
+}
+
+fn char_class(mut char_ranges: Vec<CharRange>) -> AstKind {
+    char_ranges.sort_unstable();
+    let mut merged_char_ranges: Vec<CharRange> = Vec::new();
+    for char_range in char_ranges {
+        match merged_char_ranges.last_mut() {
+            Some(last) if char_range.start as u32 <= last.end as u32 + 1 => {
+                last.end = last.end.max(char_range.end)
+            }
+            _ => merged_char_ranges.push(char_range),
+        }
+    }
+    match merged_char_ranges[..] {
+        [CharRange { start, end }] if start == end => AstKind::Literal(start),
+        _ => AstKind::Class(Class::Chars(merged_char_ranges)),
+    }
+}
+
+fn byte_class(mut byte_ranges: Vec<ByteRange>) -> AstKind {
+    byte_ranges.sort_unstable_by_key(|byte_range| (byte_range.start, byte_range.end));
+    let mut merged_byte_ranges: Vec<ByteRange> = Vec::new();
+    for byte_range in byte_ranges {
+        match merged_byte_ranges.last_mut() {
+            Some(last) if byte_range.start as u32 <= last.end as u32 + 1 => {
+                last.end = last.end.max(byte_range.end)
+            }
+            _ => merged_byte_ranges.push(byte_range),
+        }
+    }
+    match merged_byte_ranges[..] {
+        [ByteRange { start, end }] if start == end => AstKind::Byte(start),
+        _ => AstKind::Class(Class::Bytes(merged_byte_ranges)),
+    }
+}
+
+fn flatten(asts: Vec<Ast>, unwrap: fn(AstKind) -> Result<Vec<Ast>, AstKind>) -> Vec<Ast> {
+    let mut flattened_asts = Vec::new();
+    for ast in asts {
+        match unwrap(ast.kind) {
+            Ok(asts) => flattened_asts.extend(asts),
+            Err(kind) => flattened_asts.push(Ast::new(kind, ast.span)),
+        }
+    }
+    flattened_asts
+}
+
+fn span_of(asts: &[Ast]) -> Option<Span> {
+    Some(asts.first()?.span.to(asts.last()?.span))
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            determinizer::Determinizer,
+            parser::{self, Parser},
+        },
+    };
+
+    fn simplified(pattern: &str, match_kind: MatchKind) -> String {
+        simplify(parser::parse(pattern).unwrap(), match_kind).to_string()
+    }
+
+    #[test]
+    fn factors_common_prefixes() {
+        let simplified = |pattern| simplified(pattern, MatchKind::LeftmostLongest);
+        assert_eq!(simplified("foo|foobar"), "foo(bar)?");
+        assert_eq!(simplified("foobar|foo"), "foo(bar)?");
+        assert_eq!(simplified("abc|abd|x"), "ab[c-d]|x");
+        assert_eq!(simplified("((a))*"), "a*");
+        assert_eq!(simplified("(a*)*|(b+)+"), "a*|b+");
+    }
+
+    #[test]
+    fn preserves_priority_under_leftmost_first() {
+        let simplified = |pattern| simplified(pattern, MatchKind::LeftmostFirst);
+        assert_eq!(simplified("foo|foobar"), "foo|foobar");
+        assert_eq!(simplified("foobar|foo"), "foo(bar)?");
+        assert_eq!(simplified("abc|abd|x"), "ab[c-d]|x");
+    }
+
+    #[test]
+    fn simplified_patterns_match_the_same_strings() {
+        for match_kind in [MatchKind::LeftmostLongest, MatchKind::LeftmostFirst] {
+            for pattern in ["foo|foobar", "foobar|foo", "a(b|c)|ad|(x)*", "[a-c]|b|d"] {
+                let dfa = |pattern: &str| {
+                    let mut parser = Parser::new();
+                    parser.set_match_kind(match_kind);
+                    parser.parse(pattern, 0).unwrap();
+                    Determinizer::new(&parser.build())
+                        .match_kind(match_kind)
+                        .determinize()
+                };
+                let simplified = simplified(pattern, match_kind);
+                for text in [&b"foobar"[..], b"foo", b"ab", b"adx", b"xx", b"b"] {
+                    let longest_match = |dfa: &crate::dfa::Dfa| {
+                        dfa.longest_match(text.iter().cloned())
+                            .map(|(token, rest)| (token, rest.len()))
+                    };
+                    assert_eq!(
+                        longest_match(&dfa(pattern)),
+                        longest_match(&dfa(&simplified)),
+                        "{} {}",
+                        pattern,
+                        simplified
+                    );
+                }
+            }
+        }
+    }
+}
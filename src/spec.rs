@@ -0,0 +1,377 @@
+use {
+    crate::{
+        ast::{Ast, AstKind, Span},
+        parser::{self, Parser},
+        token::{Token, TokenTable},
+    },
+    std::{collections::HashMap, error, fmt, fs, io, path::Path},
+};
+
+#[derive(Clone, Debug)]
+pub struct Spec {
+    definitions: Vec<(String, String)>,
+    rules: Vec<Rule>,
+    tokens: TokenTable,
+    mode_names: Vec<String>,
+    mode_ids_by_name: HashMap<String, usize>,
+}
+
+impl Spec {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::parse(&fs::read_to_string(path).map_err(Error::Io)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut spec = Self {
+            definitions: Vec::new(),
+            rules: Vec::new(),
+            tokens: TokenTable::new(),
+            mode_names: Vec::new(),
+            mode_ids_by_name: HashMap::new(),
+        };
+        spec.add_mode(INITIAL_MODE);
+        let mut parser = Parser::new();
+        let mut in_rules = false;
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line == "%%" {
+                if in_rules {
+                    return Err(Error::Line(line_number, ErrorKind::UnexpectedSeparator));
+                }
+                in_rules = true;
+                continue;
+            }
+            if in_rules {
+                let rule = spec
+                    .parse_rule(line, line_number)
+                    .map_err(|kind| Error::Line(line_number, kind))?;
+                match &rule.pattern {
+                    Pattern::Regex(pattern) => parser.parse(pattern, rule.token),
+                    Pattern::Literal(_) => Ok(()),
+                }
+                .map_err(|error| Error::Line(line_number, ErrorKind::Parse(error)))?;
+                spec.rules.push(rule);
+            } else {
+                let (name, pattern) = parse_definition(line)
+                    .ok_or(Error::Line(line_number, ErrorKind::InvalidDefinition))?;
+                parser
+                    .define(name, pattern)
+                    .map_err(|error| Error::Line(line_number, ErrorKind::Parse(error)))?;
+                spec.definitions
+                    .push((name.to_string(), pattern.to_string()));
+            }
+        }
+        spec.set_token_patterns();
+        Ok(spec)
+    }
+
+    pub fn definitions(&self) -> &[(String, String)] {
+        &self.definitions
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    pub fn tokens(&self) -> &TokenTable {
+        &self.tokens
+    }
+
+    pub fn mode_count(&self) -> usize {
+        self.mode_names.len()
+    }
+
+    pub fn mode_id(&self, name: &str) -> Option<usize> {
+        self.mode_ids_by_name.get(name).cloned()
+    }
+
+    pub fn mode_name(&self, mode: usize) -> Option<&str> {
+        self.mode_names.get(mode).map(String::as_str)
+    }
+
+    pub fn parser(&self, mode: usize) -> Result<Parser, parser::Error> {
+        let mut parser = Parser::new();
+        *parser.tokens_mut() = self.tokens.clone();
+        for (name, pattern) in &self.definitions {
+            parser.define(name, pattern)?;
+        }
+        for rule in self.rules.iter().filter(|rule| rule.modes.contains(&mode)) {
+            match &rule.pattern {
+                Pattern::Regex(pattern) => parser.parse(pattern, rule.token)?,
+                Pattern::Literal(text) => parser.literal(text, rule.token),
+            }
+        }
+        Ok(parser)
+    }
+
+    fn parse_rule(&mut self, line: &str, line_number: usize) -> Result<Rule, ErrorKind> {
+        let (pattern, rest) = split_pattern(line)?;
+        let mut words = rest
+            .split_whitespace()
+            .take_while(|word| !word.starts_with('#'))
+            .peekable();
+        let token_name = words.next().ok_or(ErrorKind::MissingTokenName)?;
+        let token = self.add_token(token_name);
+        let mut modes = Vec::new();
+        while let Some(word) = words.next() {
+            match word {
+                "skip" => self.tokens.get_mut(token).unwrap().skip = true,
+                "mode" => {
+                    let mode_count = modes.len();
+                    while let Some(mode_name) = words.next_if(|word| !FLAGS.contains(word)) {
+                        modes.push(self.add_mode(mode_name));
+                    }
+                    if modes.len() == mode_count {
+                        return Err(ErrorKind::MissingModeName);
+                    }
+                }
+                _ => return Err(ErrorKind::UnknownFlag(word.to_string())),
+            }
+        }
+        if modes.is_empty() {
+            modes.push(self.mode_ids_by_name[INITIAL_MODE]);
+        }
+        Ok(Rule {
+            pattern,
+            token,
+            modes,
+            line: line_number,
+        })
+    }
+
+    fn add_token(&mut self, name: &str) -> usize {
+        if let Some(token) = self.tokens.token_id(name) {
+            return token;
+        }
+        let token = self.tokens.len();
+        self.tokens.insert(
+            token,
+            Token {
+                name: name.to_string(),
+                pattern: String::new(),
+                priority: token,
+                skip: false,
+            },
+        );
+        token
+    }
+
+    fn set_token_patterns(&mut self) {
+        let mut patterns = vec![Vec::new(); self.tokens.len()];
+        for rule in &self.rules {
+            patterns[rule.token].push(rule.pattern.to_string());
+        }
+        for (token, patterns) in patterns.into_iter().enumerate() {
+            self.tokens.get_mut(token).unwrap().pattern = match patterns.len() {
+                1 => patterns.into_iter().next().unwrap(),
+                _ => patterns
+                    .iter()
+                    .map(|pattern| format!("({})", pattern))
+                    .collect::<Vec<_>>()
+                    .join("|"),
+            };
+        }
+    }
+
+    fn add_mode(&mut self, name: &str) -> usize {
+        if let Some(&mode) = self.mode_ids_by_name.get(name) {
+            return mode;
+        }
+        let mode = self.mode_names.len();
+        self.mode_names.push(name.to_string());
+        self.mode_ids_by_name.insert(name.to_string(), mode);
+        mode
+    }
+}
+
+pub const INITIAL_MODE: &str = "INITIAL";
+
+const FLAGS: &[&str] = &["skip", "mode"];
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Rule {
+    pub pattern: Pattern,
+    pub token: usize,
+    pub modes: Vec<usize>,
+    pub line: usize,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Pattern {
+    Regex(String),
+    Literal(String),
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Regex(pattern) => write!(f, "{}", pattern),
+            Self::Literal(text) => write!(
+                f,
+                "{}",
+                Ast::new(AstKind::String(text.clone()), Span::default())
+            ),
+        }
+    }
+}
+
+fn parse_definition(line: &str) -> Option<(&str, &str)> {
+    let (name, pattern) = line.split_once(char::is_whitespace)?;
+    Some((name, pattern.trim()))
+}
+
+fn split_pattern(line: &str) -> Result<(Pattern, &str), ErrorKind> {
+    if let Some(rest) = line.strip_prefix('"') {
+        let mut text = String::new();
+        let mut chars = rest.char_indices();
+        while let Some((index, ch)) = chars.next() {
+            match ch {
+                '"' => return Ok((Pattern::Literal(text), &rest[index + 1..])),
+                '\\' => text.push(match chars.next() {
+                    Some((_, 'n')) => '\n',
+                    Some((_, 'r')) => '\r',
+                    Some((_, 't')) => '\t',
+                    Some((_, ch @ ('"' | '\\'))) => ch,
+                    _ => return Err(ErrorKind::InvalidLiteral),
+                }),
+                ch => text.push(ch),
+            }
+        }
+        return Err(ErrorKind::InvalidLiteral);
+    }
+    let mut in_class = false;
+    let mut chars = line.char_indices();
+    while let Some((index, ch)) = chars.next() {
+        match ch {
+            '\\' => {
+                chars.next();
+            }
+            '[' => in_class = true,
+            ']' => in_class = false,
+            ch if ch.is_whitespace() && !in_class => {
+                return Ok((Pattern::Regex(line[..index].to_string()), &line[index..]))
+            }
+            _ => {}
+        }
+    }
+    Err(ErrorKind::MissingTokenName)
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Line(usize, ErrorKind),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "{}", error),
+            Self::Line(line, kind) => write!(f, "line {}: {}", line, kind),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Io(error) => Some(error),
+            Self::Line(_, ErrorKind::Parse(error)) => Some(error),
+            Self::Line(..) => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ErrorKind {
+    Parse(parser::Error),
+    InvalidDefinition,
+    InvalidLiteral,
+    MissingTokenName,
+    MissingModeName,
+    UnknownFlag(String),
+    UnexpectedSeparator,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Parse(error) => write!(f, "{}", error),
+            Self::InvalidDefinition => write!(f, "expected a name followed by a pattern"),
+            Self::InvalidLiteral => write!(f, "invalid quoted literal"),
+            Self::MissingTokenName => write!(f, "missing token name"),
+            Self::MissingModeName => write!(f, "missing mode name"),
+            Self::UnknownFlag(flag) => write!(f, "unknown flag `{}`", flag),
+            Self::UnexpectedSeparator => write!(f, "unexpected `%%`"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::regex::LexerBuilder};
+
+    #[test]
+    fn mode_names_end_at_the_next_flag() {
+        let spec =
+            Spec::parse("%%\n[a-z]+ IDENT mode A B skip\n[0-9]+ NUMBER skip mode B\n").unwrap();
+        let mode_ids = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| spec.mode_id(name).unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(spec.mode_count(), 3);
+        assert_eq!(spec.rules()[0].modes, mode_ids(&["A", "B"]));
+        assert_eq!(spec.rules()[1].modes, mode_ids(&["B"]));
+        assert!(spec.tokens().get(0).unwrap().skip);
+        assert!(spec.tokens().get(1).unwrap().skip);
+    }
+
+    #[test]
+    fn builds_a_parser_per_mode() {
+        let spec = Spec::parse(
+            "DIGIT [0-9]\n%%\n{DIGIT}+ NUMBER\n\"if\" IF mode A\n[a-z]+ IDENT mode INITIAL A\n\\x20+ SPACE skip mode INITIAL A\n",
+        )
+        .unwrap();
+        let tokens = |mode, haystack| {
+            let lexer = LexerBuilder::from_spec(&spec, mode)
+                .unwrap()
+                .build()
+                .unwrap();
+            lexer
+                .find_iter(haystack)
+                .map(|match_| spec.tokens().name(match_.token).unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        let initial = spec.mode_id(INITIAL_MODE).unwrap();
+        let a = spec.mode_id("A").unwrap();
+        assert_eq!(tokens(initial, &b"12 if"[..]), vec!["NUMBER", "IDENT"]);
+        assert_eq!(tokens(a, &b"if x"[..]), vec!["IF", "IDENT"]);
+    }
+
+    #[test]
+    fn rejects_malformed_rules() {
+        let error = |text| match Spec::parse(text) {
+            Err(Error::Line(line, kind)) => (line, kind),
+            result => panic!("{:?}", result),
+        };
+        assert_eq!(error("%%\na IDENT mode\n"), (2, ErrorKind::MissingModeName));
+        assert_eq!(
+            error("%%\na IDENT mode skip\n"),
+            (2, ErrorKind::MissingModeName)
+        );
+        assert_eq!(
+            error("%%\na IDENT loud\n"),
+            (2, ErrorKind::UnknownFlag("loud".to_string()))
+        );
+        assert_eq!(error("%%\na\n"), (2, ErrorKind::MissingTokenName));
+        assert_eq!(error("%%\n%%\n"), (2, ErrorKind::UnexpectedSeparator));
+        assert_eq!(error("DIGIT\n"), (1, ErrorKind::InvalidDefinition));
+        assert_eq!(error("%%\n\"ab IDENT\n"), (2, ErrorKind::InvalidLiteral));
+    }
+}
@@ -0,0 +1,420 @@
+use {
+    crate::{
+        analysis,
+        dfa::{self, Dfa, StateId, StateIdRepr},
+    },
+    std::{
+        collections::{BTreeMap, BTreeSet},
+        fmt::{self, Write},
+        mem, slice,
+    },
+};
+
+pub struct StateEliminator {
+    outgoing_edges: Vec<BTreeMap<StateId, Expr>>,
+    incoming_edges: Vec<BTreeSet<StateId>>,
+    remaining_state_ids: BTreeSet<StateId>,
+}
+
+impl StateEliminator {
+    pub fn new<S: StateIdRepr>(dfa: &Dfa<S>, token: Option<usize>) -> Self {
+        let reachable_states = analysis::reachable_states(dfa);
+        let co_reachable_states = analysis::co_reachable_states(dfa, token);
+        let is_useful =
+            |state_id: StateId| reachable_states[state_id] && co_reachable_states[state_id];
+        let initial_state_id = dfa.state_count();
+        let final_state_id = dfa.state_count() + 1;
+        let mut state_eliminator = Self {
+            outgoing_edges: vec![BTreeMap::new(); dfa.state_count() + 2],
+            incoming_edges: vec![BTreeSet::new(); dfa.state_count() + 2],
+            remaining_state_ids: (0..dfa.state_count())
+                .filter(|&state_id| is_useful(state_id))
+                .collect(),
+        };
+        if !state_eliminator
+            .remaining_state_ids
+            .contains(&dfa::start_state_id())
+        {
+            return state_eliminator;
+        }
+        state_eliminator.add_edge(initial_state_id, dfa::start_state_id(), Expr::Empty);
+        for (state_id, state) in dfa.states().filter(|&(state_id, _)| is_useful(state_id)) {
+            let mut byte_sets = BTreeMap::new();
+            for transition in state.transitions() {
+                if is_useful(transition.next_state_id) {
+                    byte_sets
+                        .entry(transition.next_state_id)
+                        .or_insert_with(ByteSet::new)
+                        .insert(transition.byte);
+                }
+            }
+            for (next_state_id, byte_set) in byte_sets {
+                state_eliminator.add_edge(state_id, next_state_id, Expr::Class(byte_set));
+            }
+            if analysis::accepts(state.matched_token(), token) {
+                state_eliminator.add_edge(state_id, final_state_id, Expr::Empty);
+            }
+        }
+        state_eliminator
+    }
+
+    pub fn eliminate(mut self) -> Option<String> {
+        let initial_state_id = self.outgoing_edges.len() - 2;
+        let final_state_id = self.outgoing_edges.len() - 1;
+        while let Some(state_id) = self.cheapest_state_id() {
+            self.eliminate_state(state_id);
+        }
+        let expr = self.outgoing_edges[initial_state_id].remove(&final_state_id)?;
+        let mut pattern = String::new();
+        write!(pattern, "{}", expr).unwrap();
+        Some(pattern)
+    }
+
+    fn cheapest_state_id(&self) -> Option<StateId> {
+        self.remaining_state_ids
+            .iter()
+            .cloned()
+            .min_by_key(|&state_id| {
+                self.incoming_edges[state_id].len() * self.outgoing_edges[state_id].len()
+            })
+    }
+
+    fn eliminate_state(&mut self, state_id: StateId) {
+        self.remaining_state_ids.remove(&state_id);
+        let loop_expr = self.outgoing_edges[state_id]
+            .remove(&state_id)
+            .map(Expr::zero_or_more);
+        self.incoming_edges[state_id].remove(&state_id);
+        let outgoing_edges = mem::take(&mut self.outgoing_edges[state_id]);
+        let incoming_state_ids = mem::take(&mut self.incoming_edges[state_id]);
+        for &next_state_id in outgoing_edges.keys() {
+            self.incoming_edges[next_state_id].remove(&state_id);
+        }
+        for previous_state_id in incoming_state_ids {
+            let previous_expr = self.outgoing_edges[previous_state_id]
+                .remove(&state_id)
+                .unwrap();
+            for (&next_state_id, next_expr) in &outgoing_edges {
+                let mut exprs = vec![previous_expr.clone()];
+                exprs.extend(loop_expr.clone());
+                exprs.push(next_expr.clone());
+                self.add_edge(previous_state_id, next_state_id, Expr::concatenate(exprs));
+            }
+        }
+    }
+
+    fn add_edge(&mut self, state_id: StateId, next_state_id: StateId, expr: Expr) {
+        let expr = match self.outgoing_edges[state_id].remove(&next_state_id) {
+            Some(other_expr) => Expr::alternate(vec![other_expr, expr]),
+            None => expr,
+        };
+        self.outgoing_edges[state_id].insert(next_state_id, expr);
+        self.incoming_edges[next_state_id].insert(state_id);
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Expr {
+    Empty,
+    Class(ByteSet),
+    Concatenate(Vec<Expr>),
+    Alternate(Vec<Expr>),
+    ZeroOrMore(Box<Expr>),
+}
+
+impl Expr {
+    fn concatenate(exprs: Vec<Expr>) -> Expr {
+        let mut flattened_exprs = Vec::new();
+        for expr in exprs {
+            match expr {
+                Expr::Empty => {}
+                Expr::Concatenate(exprs) => flattened_exprs.extend(exprs),
+                expr => flattened_exprs.push(expr),
+            }
+        }
+        match flattened_exprs.len() {
+            0 => Expr::Empty,
+            1 => flattened_exprs.pop().unwrap(),
+            _ => Expr::Concatenate(flattened_exprs),
+        }
+    }
+
+    fn alternate(exprs: Vec<Expr>) -> Expr {
+        let mut flattened_exprs: Vec<Expr> = Vec::new();
+        let mut byte_set: Option<ByteSet> = None;
+        for expr in exprs {
+            let exprs = match expr {
+                Expr::Alternate(exprs) => exprs,
+                expr => vec![expr],
+            };
+            for expr in exprs {
+                match expr {
+                    Expr::Class(other_byte_set) => {
+                        byte_set
+                            .get_or_insert_with(ByteSet::new)
+                            .union(&other_byte_set);
+                    }
+                    expr if !flattened_exprs.contains(&expr) => flattened_exprs.push(expr),
+                    _ => {}
+                }
+            }
+        }
+        if let Some(byte_set) = byte_set {
+            flattened_exprs.insert(0, Expr::Class(byte_set));
+        }
+        if let Some((index_0, index_1, prefix)) = find_common_suffix(&flattened_exprs) {
+            let suffix = flattened_exprs[index_0].clone();
+            flattened_exprs.remove(index_0.max(index_1));
+            flattened_exprs.remove(index_0.min(index_1));
+            flattened_exprs.push(Expr::concatenate(vec![
+                Expr::alternate(vec![prefix, Expr::Empty]),
+                suffix,
+            ]));
+            return Expr::alternate(flattened_exprs);
+        }
+        if flattened_exprs.contains(&Expr::Empty) {
+            for expr in &mut flattened_exprs {
+                if let Expr::Concatenate(exprs) = expr {
+                    if let [expr_0, Expr::ZeroOrMore(expr_1)] = &exprs[..] {
+                        if expr_0 == &**expr_1 {
+                            *expr = Expr::ZeroOrMore(expr_1.clone());
+                        }
+                    }
+                }
+            }
+            if flattened_exprs
+                .iter()
+                .any(|expr| matches!(expr, Expr::ZeroOrMore(_)))
+            {
+                flattened_exprs.retain(|expr| expr != &Expr::Empty);
+            }
+        }
+        match flattened_exprs.len() {
+            1 => flattened_exprs.pop().unwrap(),
+            _ => Expr::Alternate(flattened_exprs),
+        }
+    }
+
+    fn as_slice(&self) -> &[Expr] {
+        match self {
+            Expr::Concatenate(exprs) => exprs,
+            expr => slice::from_ref(expr),
+        }
+    }
+
+    fn zero_or_more(self) -> Expr {
+        match self {
+            Expr::Empty => Expr::Empty,
+            expr @ Expr::ZeroOrMore(_) => expr,
+            Expr::Alternate(exprs) => Expr::ZeroOrMore(Box::new(Expr::alternate(
+                exprs
+                    .into_iter()
+                    .map(|expr| match expr {
+                        Expr::ZeroOrMore(expr) => *expr,
+                        expr => expr,
+                    })
+                    .filter(|expr| expr != &Expr::Empty)
+                    .collect(),
+            ))),
+            expr => Expr::ZeroOrMore(Box::new(expr)),
+        }
+    }
+
+    fn precedence(&self) -> u32 {
+        match self {
+            Expr::Alternate(exprs) if !exprs.contains(&Expr::Empty) => 0,
+            Expr::Concatenate(_) => 1,
+            _ => 2,
+        }
+    }
+
+    fn fmt_with_precedence(&self, f: &mut fmt::Formatter, precedence: u32) -> fmt::Result {
+        if self.precedence() < precedence {
+            write!(f, "({})", self)
+        } else {
+            write!(f, "{}", self)
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Empty => write!(f, "()"),
+            Expr::Class(byte_set) => write!(f, "{}", byte_set),
+            Expr::Concatenate(exprs) => {
+                let mut index = 0;
+                while index < exprs.len() {
+                    match exprs.get(index + 1) {
+                        Some(Expr::ZeroOrMore(expr)) if **expr == exprs[index] => {
+                            exprs[index].fmt_with_precedence(f, 2)?;
+                            write!(f, "+")?;
+                            index += 2;
+                        }
+                        _ => {
+                            exprs[index].fmt_with_precedence(f, 1)?;
+                            index += 1;
+                        }
+                    }
+                }
+                Ok(())
+            }
+            Expr::Alternate(exprs) if exprs.contains(&Expr::Empty) => {
+                let expr = Expr::alternate(
+                    exprs
+                        .iter()
+                        .filter(|expr| **expr != Expr::Empty)
+                        .cloned()
+                        .collect(),
+                );
+                expr.fmt_with_precedence(f, 2)?;
+                write!(f, "?")
+            }
+            Expr::Alternate(exprs) => {
+                for (index, expr) in exprs.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, "|")?;
+                    }
+                    expr.fmt_with_precedence(f, 1)?;
+                }
+                Ok(())
+            }
+            Expr::ZeroOrMore(expr) => {
+                expr.fmt_with_precedence(f, 2)?;
+                write!(f, "*")
+            }
+        }
+    }
+}
+
+fn find_common_suffix(exprs: &[Expr]) -> Option<(usize, usize, Expr)> {
+    for (index_0, expr_0) in exprs.iter().enumerate() {
+        if expr_0 == &Expr::Empty {
+            continue;
+        }
+        let suffix = expr_0.as_slice();
+        for (index_1, expr_1) in exprs.iter().enumerate() {
+            let exprs_1 = expr_1.as_slice();
+            if exprs_1.len() > suffix.len() && exprs_1.ends_with(suffix) {
+                let prefix = exprs_1[..exprs_1.len() - suffix.len()].to_vec();
+                return Some((index_0, index_1, Expr::concatenate(prefix)));
+            }
+        }
+    }
+    None
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct ByteSet([u64; 4]);
+
+impl ByteSet {
+    fn new() -> Self {
+        Self([0; 4])
+    }
+
+    fn contains(&self, byte: u8) -> bool {
+        self.0[byte as usize / 64] & 1 << (byte % 64) != 0
+    }
+
+    fn insert(&mut self, byte: u8) {
+        self.0[byte as usize / 64] |= 1 << (byte % 64);
+    }
+
+    fn union(&mut self, other: &Self) {
+        for (word, other_word) in self.0.iter_mut().zip(&other.0) {
+            *word |= other_word;
+        }
+    }
+
+    fn ranges(&self) -> Vec<(u8, u8)> {
+        let mut ranges: Vec<(u8, u8)> = Vec::new();
+        for byte in (0..=u8::MAX).filter(|&byte| self.contains(byte)) {
+            match ranges.last_mut() {
+                Some((_, end)) if *end as u16 + 1 == byte as u16 => *end = byte,
+                _ => ranges.push((byte, byte)),
+            }
+        }
+        ranges
+    }
+}
+
+impl fmt::Display for ByteSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ranges = self.ranges();
+        if let [(start, end)] = ranges[..] {
+            if start == end {
+                return fmt_byte(f, start, "$()*+/?[]\\^{|");
+            }
+        }
+        write!(f, "[")?;
+        for (start, end) in ranges {
+            fmt_byte(f, start, "]-^\\")?;
+            if start < end {
+                if start + 1 < end {
+                    write!(f, "-")?;
+                }
+                fmt_byte(f, end, "]-^\\")?;
+            }
+        }
+        write!(f, "]")
+    }
+}
+
+fn fmt_byte(f: &mut fmt::Formatter, byte: u8, metacharacters: &str) -> fmt::Result {
+    let ch = byte as char;
+    if !byte.is_ascii_graphic() && byte != b' ' {
+        write!(f, "\\x{:02X}", byte)
+    } else if metacharacters.contains(ch) {
+        write!(f, "\\{}", ch)
+    } else {
+        write!(f, "{}", ch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{determinizer::Determinizer, parser::Parser},
+    };
+
+    fn literal(bytes: &[u8]) -> Expr {
+        Expr::concatenate(
+            bytes
+                .iter()
+                .map(|&byte| {
+                    let mut byte_set = ByteSet::new();
+                    byte_set.insert(byte);
+                    Expr::Class(byte_set)
+                })
+                .collect(),
+        )
+    }
+
+    fn dfa(pattern: &str) -> Dfa {
+        let mut parser = Parser::new();
+        parser.parse(pattern, 0).unwrap();
+        Determinizer::new(&parser.build()).determinize()
+    }
+
+    #[test]
+    fn alternate_factors_common_suffixes() {
+        let expr = Expr::alternate(vec![literal(b"abc"), literal(b"bc")]);
+        assert_eq!(expr.to_string(), "a?bc");
+        let expr = Expr::alternate(vec![literal(b"bc"), literal(b"abc")]);
+        assert_eq!(expr.to_string(), "a?bc");
+    }
+
+    #[test]
+    fn round_trips_through_a_pattern() {
+        for pattern in &["abc|bc", "a(b|c)*d", "[0-9]+(,[0-9]+)?", "x|yz*"] {
+            let dfa = dfa(pattern);
+            let pattern = dfa.to_pattern(Some(0)).unwrap();
+            assert!(
+                self::dfa(&pattern).is_equivalent(&dfa).is_ok(),
+                "{}",
+                pattern
+            );
+        }
+    }
+}
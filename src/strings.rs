@@ -0,0 +1,138 @@
+use crate::{
+    analysis,
+    dfa::{Dfa, StateId, StateIdRepr, ALPHABET_LEN},
+};
+
+#[derive(Debug)]
+pub struct Strings<'a, S> {
+    dfa: &'a Dfa<S>,
+    start_state_id: StateId,
+    max_length: usize,
+    can_accept: Vec<Vec<bool>>,
+    length: usize,
+    state_stack: Vec<(StateId, usize)>,
+    bytes: Vec<u8>,
+}
+
+impl<'a, S: StateIdRepr> Strings<'a, S> {
+    pub fn new(dfa: &'a Dfa<S>, token: Option<usize>, max_length: usize) -> Self {
+        let mut strings = Self {
+            dfa,
+            start_state_id: dfa.start_state_id_after(None),
+            max_length,
+            can_accept: can_accept(dfa, token, max_length),
+            length: 0,
+            state_stack: Vec::new(),
+            bytes: Vec::new(),
+        };
+        if strings.can_accept[0][strings.start_state_id] {
+            strings.state_stack.push((strings.start_state_id, 0));
+        }
+        strings
+    }
+}
+
+impl<'a, S: StateIdRepr> Iterator for Strings<'a, S> {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            while let Some((state_id, byte)) = self.state_stack.last_mut() {
+                let depth = self.bytes.len();
+                if depth == self.length {
+                    let bytes = self.bytes.clone();
+                    self.state_stack.pop();
+                    self.bytes.pop();
+                    return Some(bytes);
+                }
+                if *byte == ALPHABET_LEN {
+                    self.state_stack.pop();
+                    self.bytes.pop();
+                    continue;
+                }
+                let next_byte = *byte as u8;
+                *byte += 1;
+                let next_state_id = self.dfa.next_state_id(*state_id, next_byte);
+                if self.can_accept[self.length - depth - 1][next_state_id] {
+                    self.bytes.push(next_byte);
+                    self.state_stack.push((next_state_id, 0));
+                }
+            }
+            if self.length == self.max_length {
+                return None;
+            }
+            self.length += 1;
+            if self.can_accept[self.length][self.start_state_id] {
+                self.state_stack.push((self.start_state_id, 0));
+            }
+        }
+    }
+}
+
+fn can_accept<S: StateIdRepr>(
+    dfa: &Dfa<S>,
+    token: Option<usize>,
+    max_length: usize,
+) -> Vec<Vec<bool>> {
+    let mut can_accept = Vec::with_capacity(max_length + 1);
+    can_accept.push(
+        dfa.states()
+            .map(|(_, state)| analysis::accepts(state.matched_token_before(None), token))
+            .collect::<Vec<_>>(),
+    );
+    for length in 1..=max_length {
+        let previous_can_accept = &can_accept[length - 1];
+        let next_can_accept = dfa
+            .states()
+            .map(|(_, state)| {
+                state
+                    .transitions()
+                    .any(|transition| previous_can_accept[transition.next_state_id])
+            })
+            .collect::<Vec<_>>();
+        can_accept.push(next_can_accept);
+    }
+    can_accept
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{determinizer::Determinizer, parser::Parser};
+
+    fn strings(pattern: &str, max_length: usize) -> Vec<Vec<u8>> {
+        let mut parser = Parser::new();
+        parser.parse(pattern, 0).unwrap();
+        let dfa = Determinizer::new(&parser.build()).determinize();
+        dfa.strings(Some(0), max_length).collect()
+    }
+
+    #[test]
+    fn enumerates_in_shortlex_order() {
+        assert_eq!(
+            strings("b|(a|b)(a|b)", 2),
+            vec![
+                b"b".to_vec(),
+                b"aa".to_vec(),
+                b"ab".to_vec(),
+                b"ba".to_vec(),
+                b"bb".to_vec(),
+            ]
+        );
+    }
+
+    #[test]
+    fn enumerates_strings_satisfying_assertions() {
+        assert_eq!(strings("a\\Bb?", 2), vec![b"ab".to_vec()]);
+        assert_eq!(strings("[ab]\\b", 2), vec![b"a".to_vec(), b"b".to_vec()]);
+        assert_eq!(strings("^$|x\\B\\n", 2), vec![b"".to_vec()]);
+    }
+
+    #[test]
+    fn respects_max_length() {
+        assert_eq!(
+            strings("a+", 3),
+            vec![b"a".to_vec(), b"aa".to_vec(), b"aaa".to_vec()]
+        );
+        assert!(strings("abc", 2).is_empty());
+    }
+}
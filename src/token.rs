@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Token {
+    pub name: String,
+    pub pattern: String,
+    pub priority: usize,
+    pub skip: bool,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct TokenTable {
+    tokens: Vec<Option<Token>>,
+    token_ids_by_name: HashMap<String, usize>,
+}
+
+impl TokenTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    pub fn get(&self, token: usize) -> Option<&Token> {
+        self.tokens.get(token)?.as_ref()
+    }
+
+    pub fn get_mut(&mut self, token: usize) -> Option<&mut Token> {
+        self.tokens.get_mut(token)?.as_mut()
+    }
+
+    /// Returns the priority used to break ties between tokens matching the same text, lower
+    /// values winning. Tokens missing from the table fall back to their id.
+    pub fn priority(&self, token: usize) -> usize {
+        self.get(token).map_or(token, |token| token.priority)
+    }
+
+    pub fn name(&self, token: usize) -> Option<&str> {
+        self.get(token).map(|token| token.name.as_str())
+    }
+
+    pub fn token_id(&self, name: &str) -> Option<usize> {
+        self.token_ids_by_name.get(name).cloned()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &Token)> {
+        self.tokens
+            .iter()
+            .enumerate()
+            .filter_map(|(token_id, token)| Some((token_id, token.as_ref()?)))
+    }
+
+    pub fn insert(&mut self, token_id: usize, token: Token) {
+        if token_id >= self.tokens.len() {
+            self.tokens.resize(token_id + 1, None);
+        }
+        if let Some(old_token) = &self.tokens[token_id] {
+            self.token_ids_by_name.remove(&old_token.name);
+        }
+        self.token_ids_by_name.insert(token.name.clone(), token_id);
+        self.tokens[token_id] = Some(token);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{compressed_dfa::CompressedDfa, determinizer::Determinizer, parser::Parser},
+    };
+
+    fn token(name: &str, priority: usize) -> Token {
+        Token {
+            name: name.to_string(),
+            pattern: String::new(),
+            priority,
+            skip: false,
+        }
+    }
+
+    #[test]
+    fn looks_up_tokens_by_id_and_name() {
+        let mut tokens = TokenTable::new();
+        tokens.insert(2, token("IDENT", 5));
+        assert_eq!(tokens.len(), 3);
+        assert_eq!(tokens.get(0), None);
+        assert_eq!(tokens.name(2), Some("IDENT"));
+        assert_eq!(tokens.token_id("IDENT"), Some(2));
+        assert_eq!(tokens.priority(2), 5);
+        assert_eq!(tokens.priority(1), 1);
+        tokens.insert(2, token("NAME", 5));
+        assert_eq!(tokens.token_id("IDENT"), None);
+        assert_eq!(tokens.token_id("NAME"), Some(2));
+        assert_eq!(
+            tokens
+                .iter()
+                .map(|(token_id, _)| token_id)
+                .collect::<Vec<_>>(),
+            vec![2]
+        );
+    }
+
+    #[test]
+    fn tokens_are_carried_through_the_pipeline() {
+        let mut parser = Parser::new();
+        parser.parse("[a-z]+", 0).unwrap();
+        parser.tokens_mut().insert(0, token("IDENT", 0));
+        let nfa = parser.build();
+        let dfa = Determinizer::new(&nfa).determinize();
+        assert_eq!(dfa.tokens(), nfa.tokens());
+        let dfa = dfa.minimize();
+        assert_eq!(dfa.tokens(), nfa.tokens());
+        assert_eq!(dfa.to_repr::<u16>().unwrap().tokens(), nfa.tokens());
+        assert_eq!(CompressedDfa::new(&dfa).tokens(), nfa.tokens());
+    }
+}
@@ -0,0 +1,200 @@
+use crate::nfa::CharRange;
+
+mod case_folding;
+mod general_category;
+mod property;
+mod script;
+
+pub fn simple_case_folding(ch: char) -> impl Iterator<Item = char> {
+    let equivalents =
+        match case_folding::SIMPLE_CASE_FOLDING.binary_search_by_key(&ch, |&(ch, _)| ch) {
+            Ok(index) => case_folding::SIMPLE_CASE_FOLDING[index].1,
+            Err(_) => &[],
+        };
+    equivalents.iter().cloned()
+}
+
+pub fn add_simple_case_folding(char_ranges: &mut Vec<CharRange>) {
+    for index in 0..char_ranges.len() {
+        let CharRange { start, end } = char_ranges[index];
+        let start_index = case_folding::SIMPLE_CASE_FOLDING.partition_point(|&(ch, _)| ch < start);
+        for &(ch, _) in case_folding::SIMPLE_CASE_FOLDING[start_index..]
+            .iter()
+            .take_while(|&&(ch, _)| ch <= end)
+        {
+            char_ranges.extend(simple_case_folding(ch).map(|equivalent| CharRange {
+                start: equivalent,
+                end: equivalent,
+            }));
+        }
+    }
+}
+
+pub fn property(name: &str) -> Option<Vec<CharRange>> {
+    let name = name
+        .chars()
+        .filter(|&ch| !matches!(ch, ' ' | '_' | '-'))
+        .flat_map(char::to_lowercase)
+        .collect::<String>();
+    let char_ranges = [
+        general_category::BY_NAME,
+        script::BY_NAME,
+        property::BY_NAME,
+    ]
+    .iter()
+    .find_map(|by_name| {
+        let index = by_name
+            .binary_search_by_key(&name.as_str(), |&(name, _)| name)
+            .ok()?;
+        Some(by_name[index].1)
+    })?;
+    Some(
+        char_ranges
+            .iter()
+            .map(|&(start, end)| CharRange { start, end })
+            .collect(),
+    )
+}
+
+pub fn negate(char_ranges: &[CharRange]) -> Vec<CharRange> {
+    let mut char_ranges = char_ranges.to_vec();
+    char_ranges.sort_unstable();
+    let mut negated_char_ranges = Vec::new();
+    let mut start = Some('\0');
+    for char_range in char_ranges {
+        if let Some(start) = start {
+            if start < char_range.start {
+                negated_char_ranges.push(CharRange {
+                    start,
+                    end: previous_char(char_range.start),
+                });
+            }
+        }
+        start = match (start, next_char(char_range.end)) {
+            (Some(start), Some(next_start)) => Some(start.max(next_start)),
+            _ => None,
+        };
+    }
+    if let Some(start) = start {
+        negated_char_ranges.push(CharRange {
+            start,
+            end: char::MAX,
+        });
+    }
+    negated_char_ranges
+}
+
+fn previous_char(ch: char) -> char {
+    match ch {
+        '\u{E000}' => '\u{D7FF}',
+        ch => char::from_u32(ch as u32 - 1).unwrap(),
+    }
+}
+
+fn next_char(ch: char) -> Option<char> {
+    match ch {
+        '\u{D7FF}' => Some('\u{E000}'),
+        ch => char::from_u32(ch as u32 + 1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{determinizer::Determinizer, parser::Parser},
+    };
+
+    fn contains(char_ranges: &[CharRange], ch: char) -> bool {
+        char_ranges
+            .iter()
+            .any(|char_range| char_range.start <= ch && ch <= char_range.end)
+    }
+
+    fn is_match(pattern: &str, text: &str) -> bool {
+        let mut parser = Parser::new();
+        parser.parse(pattern, 0).unwrap();
+        let dfa = Determinizer::new(&parser.build()).determinize();
+        dfa.longest_match(text.bytes())
+            .is_some_and(|(_, rest)| rest.count() == 0)
+    }
+
+    #[test]
+    fn case_folding_orbits_are_closed() {
+        for &(ch, equivalents) in case_folding::SIMPLE_CASE_FOLDING {
+            for &equivalent in equivalents {
+                let mut expected = simple_case_folding(ch)
+                    .filter(|&other| other != equivalent)
+                    .collect::<Vec<_>>();
+                expected.push(ch);
+                expected.sort_unstable();
+                let mut actual = simple_case_folding(equivalent).collect::<Vec<_>>();
+                actual.sort_unstable();
+                assert_eq!(actual, expected, "{:?} {:?}", ch, equivalent);
+            }
+        }
+    }
+
+    #[test]
+    fn ascii_letters_fold_to_two_element_classes() {
+        assert_eq!(simple_case_folding('k').collect::<Vec<_>>(), vec!['K']);
+        assert_eq!(simple_case_folding('\u{212A}').count(), 0);
+        assert_eq!(
+            simple_case_folding('\u{3C3}').collect::<Vec<_>>(),
+            vec!['\u{3A3}', '\u{3C2}']
+        );
+        assert!(is_match("(?i)k", "K"));
+        assert!(!is_match("(?i)k", "\u{212A}"));
+        assert!(!is_match("(?i)\u{212A}", "k"));
+    }
+
+    #[test]
+    fn case_insensitive_flags_are_scoped() {
+        assert!(is_match("(?i)abc", "aBC"));
+        assert!(is_match("a(?i:b)c", "aBc"));
+        assert!(!is_match("a(?i:b)c", "abC"));
+        assert!(is_match("(?i)a(?-i)b", "Ab"));
+        assert!(!is_match("(?i)a(?-i)b", "AB"));
+        assert!(is_match("(a(?i)b)c", "aBc"));
+        assert!(!is_match("(a(?i)b)c", "aBC"));
+        assert!(is_match("(?i)[a-c]+", "AbC"));
+        assert!(is_match("(?i)\\x41", "a"));
+        assert!(is_match("(?i)\u{3C3}", "\u{3A3}"));
+        assert!(is_match("(?i)\u{3C3}", "\u{3C2}"));
+        assert!(Parser::new().parse("(?x)a", 0).is_err());
+    }
+
+    #[test]
+    fn properties_are_looked_up_by_any_alias() {
+        let letter = property("L").unwrap();
+        assert!(contains(&letter, 'a'));
+        assert!(contains(&letter, '\u{3B1}'));
+        assert!(!contains(&letter, '1'));
+        assert_eq!(property("Decimal_Number"), property("nd"));
+        assert!(contains(&property("Greek").unwrap(), '\u{3B1}'));
+        assert!(!contains(&property("grek").unwrap(), 'a'));
+        assert!(!contains(&property("XID_Start").unwrap(), '_'));
+        assert!(contains(&property("XIDC").unwrap(), '_'));
+        assert_eq!(property("NotAProperty"), None);
+    }
+
+    #[test]
+    fn negate_complements_the_ranges() {
+        let negated = negate(&property("Nd").unwrap());
+        assert!(contains(&negated, 'a'));
+        assert!(!contains(&negated, '7'));
+        assert!(contains(&negated, '\u{E000}'));
+        assert!(contains(&negated, char::MAX));
+    }
+
+    #[test]
+    fn property_classes_match_utf8() {
+        assert!(is_match("\\p{Greek}+", "\u{3B1}\u{3B2}"));
+        assert!(!is_match("\\p{Greek}", "a"));
+        assert!(is_match("\\P{L}", "1"));
+        assert!(is_match(
+            "\\p{XID_Start}\\p{XID_Continue}*",
+            "\u{E9}t\u{E9}1"
+        ));
+    }
+}
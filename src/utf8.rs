@@ -0,0 +1,71 @@
+use crate::nfa::{ByteRange, CharRange};
+
+#[derive(Debug)]
+pub struct Utf8Sequences {
+    range_stack: Vec<(u32, u32)>,
+}
+
+impl Utf8Sequences {
+    pub fn new(char_range: CharRange) -> Self {
+        Self {
+            range_stack: vec![(char_range.start as u32, char_range.end as u32)],
+        }
+    }
+}
+
+impl Iterator for Utf8Sequences {
+    type Item = Vec<ByteRange>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        'outer: while let Some((start, end)) = self.range_stack.pop() {
+            if start > end {
+                continue;
+            }
+            if start < 0xD800 && end > 0xDFFF {
+                self.range_stack.push((0xE000, end));
+                self.range_stack.push((start, 0xD7FF));
+                continue;
+            }
+            for &max in &[0x7F, 0x7FF, 0xFFFF] {
+                if start <= max && max < end {
+                    self.range_stack.push((max + 1, end));
+                    self.range_stack.push((start, max));
+                    continue 'outer;
+                }
+            }
+            for shift in 1..4 {
+                let mask = (1 << (6 * shift)) - 1;
+                if start & !mask != end & !mask {
+                    if start & mask != 0 {
+                        self.range_stack.push(((start | mask) + 1, end));
+                        self.range_stack.push((start, start | mask));
+                        continue 'outer;
+                    }
+                    if end & mask != mask {
+                        self.range_stack.push((end & !mask, end));
+                        self.range_stack.push((start, (end & !mask) - 1));
+                        continue 'outer;
+                    }
+                }
+            }
+            let mut start_bytes = [0; 4];
+            let mut end_bytes = [0; 4];
+            let start_bytes = char::from_u32(start)
+                .unwrap()
+                .encode_utf8(&mut start_bytes)
+                .as_bytes();
+            let end_bytes = char::from_u32(end)
+                .unwrap()
+                .encode_utf8(&mut end_bytes)
+                .as_bytes();
+            return Some(
+                start_bytes
+                    .iter()
+                    .zip(end_bytes)
+                    .map(|(&start, &end)| ByteRange { start, end })
+                    .collect(),
+            );
+        }
+        None
+    }
+}
@@ -1,6 +1,6 @@
 use std::{cmp::Ordering, slice};
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 pub struct VecSet<T>(Vec<T>);
 
 impl<T> VecSet<T> {
@@ -8,6 +8,9 @@ impl<T> VecSet<T> {
         Self(Vec::new())
     }
 
+    /// # Safety
+    ///
+    /// The elements of `vec` must be sorted and free of duplicates.
     pub unsafe fn from_vec_unchecked(vec: Vec<T>) -> Self {
         Self(vec)
     }
@@ -97,8 +100,11 @@ impl<'a, T: Ord> Iterator for Difference<'a, T> {
         loop {
             match (self.item, self.other_item) {
                 (None, _) => break None,
-                (Some(item), None) => break Some(item),
-                (Some(item), Some(other_item)) => match item.cmp(&other_item) {
+                (Some(item), None) => {
+                    self.item = self.iter.next();
+                    break Some(item);
+                }
+                (Some(item), Some(other_item)) => match item.cmp(other_item) {
                     Ordering::Less => {
                         self.item = self.iter.next();
                         break Some(item);
@@ -106,7 +112,6 @@ impl<'a, T: Ord> Iterator for Difference<'a, T> {
                     Ordering::Equal => {
                         self.item = self.iter.next();
                         self.other_item = self.other_iter.next();
-                        break None;
                     }
                     Ordering::Greater => self.other_item = self.other_iter.next(),
                 },
@@ -137,7 +142,7 @@ impl<'a, T: Ord> Iterator for Intersection<'a, T> {
             match (self.item, self.other_item) {
                 (None, None) => break None,
                 (Some(_), None) | (None, Some(_)) => break None,
-                (Some(item), Some(other_item)) => match item.cmp(&other_item) {
+                (Some(item), Some(other_item)) => match item.cmp(other_item) {
                     Ordering::Less => self.item = self.iter.next(),
                     Ordering::Equal => {
                         self.item = self.iter.next();